@@ -0,0 +1,21 @@
+-- Add migration script here
+-- Later locations sharing a name get their id appended, so the constraint can be added. A
+-- counter follows the id when that name is taken as well
+DO $$
+DECLARE
+    duplicate RECORD;
+    candidate TEXT;
+    attempt INT;
+BEGIN
+    FOR duplicate IN SELECT l.id, l.name FROM locations l WHERE EXISTS (SELECT 1 FROM locations d WHERE d.name = l.name AND d.id < l.id) ORDER BY l.id LOOP
+        candidate := duplicate.name || ' (' || duplicate.id || ')';
+        attempt := 1;
+        WHILE EXISTS (SELECT 1 FROM locations o WHERE o.name = candidate) LOOP
+            attempt := attempt + 1;
+            candidate := duplicate.name || ' (' || duplicate.id || ', ' || attempt || ')';
+        END LOOP;
+        UPDATE locations SET name = candidate WHERE id = duplicate.id;
+    END LOOP;
+END $$;
+
+ALTER TABLE locations ADD CONSTRAINT locations_name_key UNIQUE (name)
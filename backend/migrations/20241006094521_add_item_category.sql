@@ -0,0 +1,3 @@
+-- Add migration script here
+
+ALTER TABLE items ADD COLUMN category_id INTEGER REFERENCES categories (id)
@@ -0,0 +1,3 @@
+-- Add migration script here
+
+ALTER TABLE locations ADD COLUMN parent_id INTEGER REFERENCES locations (id) ON DELETE SET NULL
@@ -0,0 +1,3 @@
+-- Add migration script here
+
+ALTER TABLE items ADD COLUMN location_id INTEGER REFERENCES locations (id) ON DELETE SET NULL
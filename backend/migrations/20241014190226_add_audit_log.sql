@@ -0,0 +1,5 @@
+-- Add migration script here
+
+CREATE TABLE audit_log(id SERIAL UNIQUE PRIMARY KEY NOT NULL, entity TEXT NOT NULL, entity_id INTEGER NOT NULL, action TEXT NOT NULL, actor TEXT NOT NULL, at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(), diff JSONB);
+
+CREATE INDEX audit_log_entity_idx ON audit_log (entity, entity_id)
@@ -0,0 +1,7 @@
+-- Add migration script here
+
+ALTER TABLE categories ADD COLUMN parent_id INTEGER REFERENCES categories (id);
+
+INSERT INTO categories (id, name, description, parent_id) VALUES (0, 'all', 'Every item', NULL);
+
+UPDATE categories SET parent_id = 0 WHERE id <> 0
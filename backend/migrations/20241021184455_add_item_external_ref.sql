@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE items ADD COLUMN external_ref TEXT UNIQUE;
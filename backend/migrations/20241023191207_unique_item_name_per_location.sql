@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Later items sharing a name in the same location get their id appended, so the index can be built
+UPDATE items i SET name = i.name || ' (' || i.id || ')' WHERE EXISTS (SELECT 1 FROM items d WHERE d.location_id = i.location_id AND lower(d.name) = lower(i.name) AND d.id < i.id);
+CREATE UNIQUE INDEX items_location_name_key ON items (location_id, lower(name));
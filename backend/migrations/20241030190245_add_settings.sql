@@ -0,0 +1,2 @@
+-- Add migration script here
+CREATE TABLE settings(key TEXT PRIMARY KEY NOT NULL, value TEXT NOT NULL);
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE items ADD COLUMN created_at TIMESTAMPTZ;
+UPDATE items SET created_at = updated_at;
+ALTER TABLE items ALTER COLUMN created_at SET DEFAULT now();
+ALTER TABLE items ALTER COLUMN created_at SET NOT NULL;
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE pictures ADD COLUMN position INTEGER;
+UPDATE pictures p SET position = n.position FROM (SELECT id, row_number() OVER (PARTITION BY item_id ORDER BY id) AS position FROM pictures) n WHERE p.id = n.id;
+ALTER TABLE pictures ALTER COLUMN position SET NOT NULL;
+CREATE UNIQUE INDEX pictures_item_id_position_key ON pictures (item_id, position);
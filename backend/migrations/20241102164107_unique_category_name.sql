@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Later categories sharing a name get their id appended, so the constraint can be added
+UPDATE categories c SET name = c.name || ' (' || c.id || ')' WHERE EXISTS (SELECT 1 FROM categories d WHERE d.name = c.name AND d.id < c.id);
+ALTER TABLE categories ADD CONSTRAINT categories_name_key UNIQUE (name);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE items ADD COLUMN picture_count BIGINT NOT NULL DEFAULT 0;
+UPDATE items i SET picture_count = (SELECT COUNT(*) FROM pictures p WHERE p.item_id = i.id);
@@ -0,0 +1,2 @@
+-- Add migration script here
+CREATE TABLE item_notes(id SERIAL UNIQUE PRIMARY KEY NOT NULL, item_id INTEGER NOT NULL REFERENCES items (id) ON DELETE CASCADE, body TEXT NOT NULL, created_at TIMESTAMPTZ NOT NULL DEFAULT now());
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE SEQUENCE items_sort_order_seq;
+ALTER TABLE items ADD COLUMN sort_order INTEGER;
+UPDATE items SET sort_order = id;
+SELECT setval('items_sort_order_seq', COALESCE(MAX(sort_order), 0) + 1, false) FROM items;
+ALTER TABLE items ALTER COLUMN sort_order SET DEFAULT nextval('items_sort_order_seq');
+ALTER TABLE items ALTER COLUMN sort_order SET NOT NULL;
+ALTER SEQUENCE items_sort_order_seq OWNED BY items.sort_order;
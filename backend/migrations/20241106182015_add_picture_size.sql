@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE pictures ADD COLUMN size BIGINT;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE settings ADD COLUMN id SERIAL UNIQUE NOT NULL;
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+/// Actor recorded for mutations made without an authenticated identity
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Kind of mutation recorded in the audit log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+/// Append-only record of a mutation made to an entity
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub entity: String,
+    pub entity_id: i32,
+    pub action: String,
+    pub actor: String,
+    pub at: DateTime<Utc>,
+    pub diff: Option<Value>,
+}
+
+impl AuditEntry {
+    /// Appends an entry to the audit log
+    pub async fn record(
+        executor: impl PgExecutor<'_>,
+        entity: &str,
+        entity_id: i32,
+        action: Action,
+        actor: &str,
+        diff: Option<Value>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO audit_log (entity, entity_id, action, actor, diff) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(entity)
+        .bind(entity_id)
+        .bind(action.as_str())
+        .bind(actor)
+        .bind(diff)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Reads the history of an entity, oldest entry first
+    pub async fn read_for_entity(
+        pool: &PgPool,
+        entity: &str,
+        entity_id: i32,
+    ) -> Result<Vec<AuditEntry>> {
+        let entries = sqlx::query_as::<_, AuditEntry>(
+            "SELECT * FROM audit_log a WHERE a.entity = $1 AND a.entity_id = $2 ORDER BY a.id",
+        )
+        .bind(entity)
+        .bind(entity_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(entries)
+    }
+}
@@ -1,6 +1,18 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{Acquire, FromRow, PgConnection, PgExecutor, PgPool, Postgres};
+
+use crate::{item::Item, upsert::Upserted};
+
+/// Id of the always present root category every other category descends from
+pub const ROOT_ID: i32 = 0;
+
+/// Largest number of items returned by [`Category::recent_items`]
+pub const MAX_RECENT_ITEMS_LIMIT: i64 = 100;
+
+/// Selects the ids of category `$1` and all categories nested below it as `subtree`
+const SUBTREE_CTE: &str = "WITH RECURSIVE subtree AS (SELECT id FROM categories WHERE id = $1 UNION SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id)";
 
 /// Category for grouping items
 #[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
@@ -8,30 +20,52 @@ pub struct Category {
     pub id: i32,
     pub name: String,
     pub description: String,
+    pub parent_id: Option<i32>,
+    /// Set by the database whenever the row changes, ignored in request bodies
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NewCategory {
     pub name: String,
     pub description: String,
+    pub parent_id: Option<i32>,
 }
 
 impl NewCategory {
     /// Creates a new [`NewCategory`].
+    #[cfg(test)]
     pub fn new(name: String, description: String) -> Self {
-        Self { name, description }
+        Self {
+            name,
+            description,
+            parent_id: None,
+        }
     }
 }
 
 impl Category {
     /// Read all categories from the database
-    pub async fn read_from_db(pool: &PgPool) -> Result<Vec<Category>> {
-        let categories = sqlx::query_as::<_, Category>("SELECT * FROM categories")
-            .fetch_all(pool)
-            .await?;
+    pub async fn read_from_db(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<Category>> {
+        let categories = sqlx::query_as::<_, Category>(
+            "SELECT * FROM categories ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
         Ok(categories)
     }
 
+    /// Count all categories in the database
+    pub async fn count(pool: &PgPool) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM categories")
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
     /// Read category by id from the database
     pub async fn read_from_db_by_id(pool: &PgPool, id: i32) -> Result<Category> {
         let category = sqlx::query_as::<_, Category>("SELECT * FROM categories l WHERE l.id = $1")
@@ -41,54 +75,233 @@ impl Category {
         Ok(category)
     }
 
-    /// Write category to database
-    pub async fn insert_into_db(pool: &PgPool, name: &str, description: &str) -> Result<()> {
-        sqlx::query("INSERT INTO categories (name, description) VALUES ($1, $2)")
-            .bind(name)
-            .bind(description)
-            .execute(pool)
+    /// Read the ancestors of a category from the root category down, ending with the category
+    /// itself. Stops at the first repeated category if parents form a cycle
+    pub async fn path(pool: &PgPool, id: i32) -> Result<Vec<Category>> {
+        let path = sqlx::query_as::<_, Category>(
+            "WITH RECURSIVE ancestors AS (SELECT c.*, 0 AS depth, ARRAY[c.id] AS visited FROM categories c WHERE c.id = $1 UNION ALL SELECT p.*, a.depth + 1, a.visited || p.id FROM categories p JOIN ancestors a ON p.id = a.parent_id WHERE p.id <> ALL(a.visited)) SELECT a.* FROM ancestors a ORDER BY a.depth DESC",
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        Ok(path)
+    }
+
+    /// Read the categories that no item belongs to, except the root category
+    pub async fn read_empty_from_db(pool: &PgPool) -> Result<Vec<Category>> {
+        let categories = sqlx::query_as::<_, Category>(
+            "SELECT * FROM categories c WHERE c.id <> $1 AND NOT EXISTS (SELECT 1 FROM items i WHERE i.category_id = c.id) ORDER BY c.id",
+        )
+        .bind(ROOT_ID)
+        .fetch_all(pool)
+        .await?;
+        Ok(categories)
+    }
+
+    /// Write category to database, placing it below the root category unless a parent is given
+    pub async fn insert_into_db(
+        executor: impl PgExecutor<'_>,
+        name: &str,
+        description: &str,
+        parent_id: Option<i32>,
+    ) -> Result<i32> {
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO categories (name, description, parent_id) VALUES ($1, $2, $3) RETURNING id",
+        )
+        .bind(name)
+        .bind(description)
+        .bind(parent_id.unwrap_or(ROOT_ID))
+        .fetch_one(executor)
+        .await?;
+        Ok(id)
+    }
+
+    /// Inserts a category unless one with the same name exists, leaving an existing one as it is
+    pub async fn ensure_by_name(
+        executor: impl PgExecutor<'_>,
+        name: &str,
+        description: &str,
+        parent_id: Option<i32>,
+    ) -> Result<Upserted> {
+        let ensured = sqlx::query_as::<_, Upserted>(
+            "INSERT INTO categories (name, description, parent_id) VALUES ($1, $2, $3) ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id, (xmax = 0) AS created",
+        )
+        .bind(name)
+        .bind(description)
+        .bind(parent_id.unwrap_or(ROOT_ID))
+        .fetch_one(executor)
+        .await?;
+        Ok(ensured)
+    }
+
+    /// Remove category from database, moving its child categories up to the root
+    pub async fn delete_from_db(
+        connection: impl Acquire<'_, Database = Postgres>,
+        id: i32,
+    ) -> Result<()> {
+        let mut transaction = connection.begin().await?;
+        Self::reparent_children_to_root(&mut transaction, id).await?;
+        sqlx::query("DELETE FROM categories l WHERE l.id = $1")
+            .bind(id)
+            .execute(&mut *transaction)
             .await?;
+        transaction.commit().await?;
         Ok(())
     }
 
-    /// Remove category from database
-    pub async fn delete_from_db(pool: &PgPool, id: i32) -> Result<()> {
-        sqlx::query("DELETE FROM categories l WHERE l.id = $1")
+    /// Points the categories directly below `id` at the root category
+    async fn reparent_children_to_root(connection: &mut PgConnection, id: i32) -> Result<()> {
+        sqlx::query(
+            "UPDATE categories SET parent_id = $1, updated_at = now() WHERE parent_id = $2",
+        )
+        .bind(ROOT_ID)
+        .bind(id)
+        .execute(connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Locks a category until the end of the transaction, failing as a missing row when there is
+    /// none. Writers placing items in the category wait for the lock
+    pub async fn lock(connection: &mut PgConnection, id: i32) -> Result<()> {
+        sqlx::query("SELECT c.id FROM categories c WHERE c.id = $1 FOR UPDATE")
             .bind(id)
-            .execute(pool)
+            .fetch_one(connection)
             .await?;
         Ok(())
     }
 
-    /// Update category in database
-    pub async fn update_in_db(pool: &PgPool, category: &Category) -> Result<()> {
-        sqlx::query("UPDATE categories SET name = $1, description = $2 WHERE id = $3")
-            .bind(&category.name)
-            .bind(&category.description)
-            .bind(category.id)
-            .execute(pool)
+    /// Takes the lock serializing moves within the category tree until the transaction ends, so
+    /// two moves can not together form a cycle
+    pub async fn lock_tree(connection: &mut PgConnection) -> Result<()> {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended('categories', 0))")
+            .execute(connection)
             .await?;
         Ok(())
     }
+
+    /// Tells whether category `other` is category `id` or nested anywhere below it
+    pub async fn in_subtree(executor: impl PgExecutor<'_>, id: i32, other: i32) -> Result<bool> {
+        let query = format!(
+            "{} SELECT EXISTS (SELECT 1 FROM subtree s WHERE s.id = $2)",
+            SUBTREE_CTE
+        );
+        let found = sqlx::query_scalar::<_, bool>(&query)
+            .bind(id)
+            .bind(other)
+            .fetch_one(executor)
+            .await?;
+        Ok(found)
+    }
+
+    /// Count the items referencing the category
+    pub async fn count_items(executor: impl PgExecutor<'_>, id: i32) -> Result<i64> {
+        let count =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM items i WHERE i.category_id = $1")
+                .bind(id)
+                .fetch_one(executor)
+                .await?;
+        Ok(count)
+    }
+
+    /// Reads the `limit` most recently added items of a category, or of it and all categories
+    /// below it when `recursive`
+    pub async fn recent_items(
+        pool: &PgPool,
+        id: i32,
+        recursive: bool,
+        limit: i64,
+    ) -> Result<Vec<Item>> {
+        let query = if recursive {
+            format!(
+                "{} SELECT i.* FROM items i JOIN subtree s ON i.category_id = s.id ORDER BY i.created_at DESC, i.id DESC LIMIT $2",
+                SUBTREE_CTE
+            )
+        } else {
+            "SELECT * FROM items i WHERE i.category_id = $1 ORDER BY i.created_at DESC, i.id DESC LIMIT $2".to_string()
+        };
+        let items = sqlx::query_as::<_, Item>(&query)
+            .bind(id)
+            .bind(limit.clamp(0, MAX_RECENT_ITEMS_LIMIT))
+            .fetch_all(pool)
+            .await?;
+        Ok(items)
+    }
+
+    /// Move the items of a category to another category, then remove it from the database. A
+    /// `reassign_to` category that does not exist fails as a missing row
+    pub async fn reassign_and_delete_from_db(
+        connection: impl Acquire<'_, Database = Postgres>,
+        id: i32,
+        reassign_to: i32,
+    ) -> Result<()> {
+        let mut transaction = connection.begin().await?;
+        sqlx::query("SELECT c.id FROM categories c WHERE c.id = $1 FOR KEY SHARE")
+            .bind(reassign_to)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(|e| {
+                anyhow::Error::from(e)
+                    .context(format!("No category {} to reassign items to", reassign_to))
+            })?;
+        sqlx::query("UPDATE items SET category_id = $1, updated_at = now() WHERE category_id = $2")
+            .bind(reassign_to)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+        Self::reparent_children_to_root(&mut transaction, id).await?;
+        sqlx::query("DELETE FROM categories l WHERE l.id = $1")
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Update category in database, unless it changed in a later second than
+    /// `unmodified_since`. A category without `parent_id` keeps its parent. Returns whether a row
+    /// was updated
+    pub async fn update_in_db(
+        executor: impl PgExecutor<'_>,
+        category: &Category,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE categories SET name = $1, description = $2, parent_id = COALESCE($3, parent_id), updated_at = now() WHERE id = $4 AND ($5::timestamptz IS NULL OR date_trunc('second', updated_at) <= $5)",
+        )
+        .bind(&category.name)
+        .bind(&category.description)
+        .bind(category.parent_id)
+        .bind(category.id)
+        .bind(unmodified_since)
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::item::{Item, NewItem};
+    use chrono::Utc;
     use sqlx::PgPool;
 
     #[sqlx::test]
     pub async fn create(pool: PgPool) {
-        Category::insert_into_db(&pool, "Books", "Place to read words")
+        Category::insert_into_db(&pool, "Books", "Place to read words", None)
             .await
             .unwrap();
 
-        let categories = Category::read_from_db(&pool).await;
+        let categories = Category::read_from_db(&pool, 100, 0).await;
 
         assert!(categories.is_ok());
         let categories = categories.unwrap();
-        let category = categories.first().unwrap();
+        let category = categories
+            .iter()
+            .find(|category| category.id != ROOT_ID)
+            .unwrap();
 
         assert_eq!(category.name, "Books".to_string());
         assert_eq!(category.description, "Place to read words".to_string());
@@ -96,7 +309,7 @@ mod tests {
 
     #[sqlx::test]
     pub async fn select_by_id(pool: PgPool) {
-        Category::insert_into_db(&pool, "Books", "Place to read words")
+        Category::insert_into_db(&pool, "Books", "Place to read words", None)
             .await
             .unwrap();
 
@@ -111,7 +324,7 @@ mod tests {
 
     #[sqlx::test]
     pub async fn delete(pool: PgPool) {
-        Category::insert_into_db(&pool, "Books", "Place to read words")
+        Category::insert_into_db(&pool, "Books", "Place to read words", None)
             .await
             .unwrap();
 
@@ -134,7 +347,7 @@ mod tests {
 
     #[sqlx::test]
     pub async fn update(pool: PgPool) {
-        Category::insert_into_db(&pool, "Books", "Place to read words")
+        Category::insert_into_db(&pool, "Books", "Place to read words", None)
             .await
             .unwrap();
 
@@ -147,7 +360,7 @@ mod tests {
         assert_eq!(category.description, "Place to read words".to_string());
 
         category.description = "Place where words with meaning are written".to_string();
-        let res = Category::update_in_db(&pool, &category).await;
+        let res = Category::update_in_db(&pool, &category, None).await;
 
         assert!(res.is_ok());
 
@@ -157,5 +370,168 @@ mod tests {
             category2.description,
             "Place where words with meaning are written".to_string()
         );
+
+        category.parent_id = None;
+        Category::update_in_db(&pool, &category, None)
+            .await
+            .unwrap();
+
+        let category3 = Category::read_from_db_by_id(&pool, 1).await.unwrap();
+        assert_eq!(category3.parent_id, Some(ROOT_ID));
+    }
+
+    #[sqlx::test]
+    pub async fn in_subtree(pool: PgPool) {
+        let books = Category::insert_into_db(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        let novels = Category::insert_into_db(&pool, "Novels", "Books with stories", Some(books))
+            .await
+            .unwrap();
+        let games = Category::insert_into_db(&pool, "Games", "Things to play", None)
+            .await
+            .unwrap();
+
+        assert!(Category::in_subtree(&pool, books, books).await.unwrap());
+        assert!(Category::in_subtree(&pool, books, novels).await.unwrap());
+        assert!(!Category::in_subtree(&pool, novels, books).await.unwrap());
+        assert!(!Category::in_subtree(&pool, books, games).await.unwrap());
+    }
+
+    #[sqlx::test]
+    pub async fn read_empty(pool: PgPool) {
+        let books = Category::insert_into_db(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        let games = Category::insert_into_db(&pool, "Games", "Things to play", None)
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(books),
+                ..NewItem::new("Dune", "Sand and worms", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let categories = Category::read_empty_from_db(&pool).await.unwrap();
+
+        assert_eq!(categories.len(), 1);
+        assert_eq!(categories[0].id, games);
+        assert_eq!(categories[0].name, "Games".to_string());
+    }
+
+    #[sqlx::test]
+    pub async fn new_category_parents_to_root(pool: PgPool) {
+        let root = Category::read_from_db_by_id(&pool, ROOT_ID).await.unwrap();
+
+        assert_eq!(root.name, "all".to_string());
+        assert_eq!(root.parent_id, None);
+
+        let books = Category::insert_into_db(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        let novels = Category::insert_into_db(&pool, "Novels", "Books with stories", Some(books))
+            .await
+            .unwrap();
+
+        let books = Category::read_from_db_by_id(&pool, books).await.unwrap();
+        assert_eq!(books.parent_id, Some(ROOT_ID));
+
+        let novels = Category::read_from_db_by_id(&pool, novels).await.unwrap();
+        assert_eq!(novels.parent_id, Some(books.id));
+    }
+
+    #[sqlx::test]
+    pub async fn path(pool: PgPool) {
+        let books = Category::insert_into_db(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        let novels = Category::insert_into_db(&pool, "Novels", "Books with stories", Some(books))
+            .await
+            .unwrap();
+        let crime = Category::insert_into_db(&pool, "Crime", "Novels with murder", Some(novels))
+            .await
+            .unwrap();
+
+        let path = Category::path(&pool, crime).await.unwrap();
+
+        let ids = path.iter().map(|c| c.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![ROOT_ID, books, novels, crime]);
+
+        sqlx::query("UPDATE categories SET parent_id = $1 WHERE id = $2")
+            .bind(crime)
+            .bind(books)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let path = Category::path(&pool, crime).await.unwrap();
+
+        assert_eq!(path.len(), 3);
+    }
+
+    #[sqlx::test]
+    pub async fn ensure_by_name(pool: PgPool) {
+        let first = Category::ensure_by_name(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        assert!(first.created);
+
+        let second = Category::ensure_by_name(&pool, "Books", "Something else", None)
+            .await
+            .unwrap();
+        assert!(!second.created);
+        assert_eq!(first.id, second.id);
+
+        let books = Category::read_from_db_by_id(&pool, first.id).await.unwrap();
+        assert_eq!(books.description, "Place to read words".to_string());
+    }
+
+    #[sqlx::test]
+    pub async fn recent_items(pool: PgPool) {
+        let books = Category::insert_into_db(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        let novels = Category::insert_into_db(&pool, "Novels", "Books with stories", Some(books))
+            .await
+            .unwrap();
+        for (name, category_id, created_at) in [
+            ("Dune", books, "2024-01-01T00:00:00Z"),
+            ("Emma", novels, "2024-03-01T00:00:00Z"),
+            ("Atlas", books, "2024-02-01T00:00:00Z"),
+        ] {
+            let id = Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    category_id: Some(category_id),
+                    ..NewItem::new(name, "Book", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+            sqlx::query("UPDATE items SET created_at = $1::timestamptz WHERE id = $2")
+                .bind(created_at)
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let names = |items: Vec<Item>| items.into_iter().map(|i| i.name).collect::<Vec<_>>();
+
+        let items = Category::recent_items(&pool, books, false, 10)
+            .await
+            .unwrap();
+        assert_eq!(names(items), vec!["Atlas", "Dune"]);
+
+        let items = Category::recent_items(&pool, books, true, 10)
+            .await
+            .unwrap();
+        assert_eq!(names(items), vec!["Emma", "Atlas", "Dune"]);
+
+        let items = Category::recent_items(&pool, books, true, 1).await.unwrap();
+        assert_eq!(names(items), vec!["Emma"]);
     }
 }
@@ -0,0 +1,128 @@
+use std::{
+    num::{NonZeroU64, NonZeroUsize},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Headers whose values never appear in logs
+pub const DEFAULT_REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "x-api-key"];
+
+/// Timeout for requests whose path starts with `prefix`, written as `prefix=ms`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteTimeout {
+    pub prefix: String,
+    pub timeout_ms: u64,
+}
+
+impl RouteTimeout {
+    /// Whether `path` is `prefix` or lies below it, so `/api/items` does not cover
+    /// `/api/items-archive`
+    pub fn matches(&self, path: &str) -> bool {
+        path.strip_prefix(&self.prefix).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/')
+        })
+    }
+}
+
+impl FromStr for RouteTimeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, timeout_ms) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Route timeout `{}` must look like `/api/items=5000`", s))?;
+        let timeout_ms = timeout_ms
+            .parse()
+            .map_err(|_| format!("Route timeout `{}` must end in milliseconds", s))?;
+        Ok(RouteTimeout {
+            prefix: prefix.to_string(),
+            timeout_ms,
+        })
+    }
+}
+
+/// Runtime configuration shared with the request handlers
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Page size used by list endpoints when no `limit` is given
+    pub default_page_size: i64,
+    /// Origins allowed to make cross-origin requests
+    pub cors_origins: Vec<String>,
+    /// Lifetime of presigned picture links, in seconds
+    pub presign_expiry_secs: u32,
+    /// `max-age` advertised on successful reads, 0 disables caching
+    pub cache_max_age_secs: u64,
+    /// Requests slower than this are logged as warnings
+    pub slow_request_ms: u64,
+    /// Detailed request logs are written for one in this many requests, 1 logs every request
+    pub log_sample_rate: NonZeroU64,
+    /// Lowercase names of headers logged as `***`
+    pub redacted_headers: Vec<String>,
+    /// Whether object storage credentials were found at startup
+    pub storage_configured: bool,
+    /// Whether JSON bodies use camelCase keys instead of snake_case
+    pub camel_case: bool,
+    /// Requests handled at once, 0 removes the limit
+    pub max_concurrent_requests: usize,
+    /// How long a request waits for a slot before being shed with 503, in milliseconds
+    pub queue_timeout_ms: u64,
+    /// Time a request may take before it is answered with 504, 0 lets requests run forever.
+    /// Multipart uploads are never timed out
+    pub request_timeout_ms: u64,
+    /// Timeouts replacing `request_timeout_ms` for some paths, the longest matching prefix wins.
+    /// Prefixes match whole path segments
+    pub route_timeouts: Vec<RouteTimeout>,
+    /// Largest JSON request body accepted by the write endpoints, in bytes. Picture uploads are
+    /// not bound by it
+    pub max_json_body_bytes: usize,
+    /// Longest name, in characters, accepted for items, locations and categories
+    pub max_name_len: usize,
+    /// Longest description, in characters, accepted for items, locations and categories
+    pub max_description_len: usize,
+    /// Object storage calls made at once
+    pub s3_max_concurrency: NonZeroUsize,
+    /// Attempts a database read made for a GET request gets when the database cannot be
+    /// reached, at least 1
+    pub db_read_attempts: u32,
+}
+
+impl Config {
+    /// Timeout for a request to `path`, `None` when it may run forever
+    pub fn timeout_for(&self, path: &str) -> Option<Duration> {
+        let timeout_ms = self
+            .route_timeouts
+            .iter()
+            .filter(|route| route.matches(path))
+            .max_by_key(|route| route.prefix.len())
+            .map_or(self.request_timeout_ms, |route| route.timeout_ms);
+        (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_page_size: 100,
+            cors_origins: Vec::new(),
+            presign_expiry_secs: 3600,
+            cache_max_age_secs: 0,
+            slow_request_ms: 1000,
+            log_sample_rate: NonZeroU64::MIN,
+            redacted_headers: DEFAULT_REDACTED_HEADERS
+                .iter()
+                .map(|header| header.to_string())
+                .collect(),
+            storage_configured: true,
+            camel_case: false,
+            max_concurrent_requests: 1024,
+            queue_timeout_ms: 100,
+            request_timeout_ms: 30_000,
+            route_timeouts: Vec::new(),
+            max_json_body_bytes: 1024 * 1024,
+            max_name_len: 255,
+            max_description_len: 4096,
+            s3_max_concurrency: NonZeroUsize::new(16).unwrap(),
+            db_read_attempts: 3,
+        }
+    }
+}
@@ -1,6 +1,15 @@
 use std::{error::Error, fmt};
 
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{
+    extract::{
+        multipart::MultipartError,
+        path::ErrorKind,
+        rejection::{JsonRejection, PathRejection},
+    },
+    http::StatusCode,
+    response::IntoResponse,
+};
+use sqlx::{error::DatabaseError, postgres::PgDatabaseError};
 
 #[derive(Debug, Clone)]
 pub struct HandlerError {
@@ -12,6 +21,36 @@ impl HandlerError {
     pub fn new(status: StatusCode, message: String) -> Self {
         Self { status, message }
     }
+
+    /// Maps a missing database row to 404, a uniqueness violation to 409, a reference to a
+    /// missing row to 400 and any other error to 500
+    pub fn from_db(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => Self::new(StatusCode::NOT_FOUND, error.to_string()),
+            Some(sqlx::Error::Database(db_error)) if db_error.is_unique_violation() => Self::new(
+                StatusCode::CONFLICT,
+                format!(
+                    "Conflicts with an existing row: {}",
+                    detail(db_error.as_ref())
+                ),
+            ),
+            Some(sqlx::Error::Database(db_error)) if db_error.is_foreign_key_violation() => {
+                Self::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Refers to a missing row: {}", detail(db_error.as_ref())),
+                )
+            }
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+        }
+    }
+}
+
+/// Postgres' explanation of a failed statement, falling back to its message
+fn detail(db_error: &dyn DatabaseError) -> &str {
+    db_error
+        .try_downcast_ref::<PgDatabaseError>()
+        .and_then(|pg_error| pg_error.detail())
+        .unwrap_or_else(|| db_error.message())
 }
 
 impl fmt::Display for HandlerError {
@@ -27,3 +66,73 @@ impl IntoResponse for HandlerError {
         (self.status, self.message).into_response()
     }
 }
+
+impl From<JsonRejection> for HandlerError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::MissingJsonContentType(_) => HandlerError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`".to_string(),
+            ),
+            JsonRejection::JsonDataError(error) => {
+                HandlerError::new(StatusCode::BAD_REQUEST, error.body_text())
+            }
+            JsonRejection::JsonSyntaxError(error) => {
+                HandlerError::new(StatusCode::BAD_REQUEST, error.body_text())
+            }
+            rejection => HandlerError::new(rejection.status(), rejection.body_text()),
+        }
+    }
+}
+
+impl From<PathRejection> for HandlerError {
+    fn from(rejection: PathRejection) -> Self {
+        let PathRejection::FailedToDeserializePathParams(error) = &rejection else {
+            return HandlerError::new(rejection.status(), rejection.body_text());
+        };
+        match error.kind() {
+            ErrorKind::ParseErrorAtKey {
+                value,
+                expected_type,
+                ..
+            }
+            | ErrorKind::ParseErrorAtIndex {
+                value,
+                expected_type,
+                ..
+            }
+            | ErrorKind::ParseError {
+                value,
+                expected_type,
+            } => HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Invalid path parameter `{}`, it must be {}",
+                    value,
+                    describe_type(expected_type)
+                ),
+            ),
+            _ => HandlerError::new(rejection.status(), rejection.body_text()),
+        }
+    }
+}
+
+/// Names a Rust type the way a client of the API would think of it
+fn describe_type(type_name: &str) -> &str {
+    match type_name {
+        "i8" | "i16" | "i32" | "i64" | "u8" | "u16" | "u32" | "u64" => "an integer",
+        other => other,
+    }
+}
+
+impl From<MultipartError> for HandlerError {
+    fn from(error: MultipartError) -> Self {
+        HandlerError::new(error.status(), error.body_text())
+    }
+}
+
+impl From<sqlx::Error> for HandlerError {
+    fn from(error: sqlx::Error) -> Self {
+        HandlerError::from_db(error.into())
+    }
+}
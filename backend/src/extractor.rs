@@ -0,0 +1,13 @@
+use axum::extract::{FromRequest, FromRequestParts};
+
+use crate::error::HandlerError;
+
+/// Json body extractor that turns rejections into [`HandlerError`]s
+#[derive(FromRequest, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::Json), rejection(HandlerError))]
+pub struct JsonBody<T>(pub T);
+
+/// Path parameter extractor that turns rejections into [`HandlerError`]s
+#[derive(FromRequestParts, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::extract::Path), rejection(HandlerError))]
+pub struct PathParam<T>(pub T);
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+use crate::picture::{MissingObject, OrphanObject, PictureInfo, S3Permits};
+
+/// Foreign key columns checked for references to rows that no longer exist, as
+/// (table, column, referenced table). Items have no gifter column, so gifters never dangle
+const FOREIGN_KEYS: [(&str, &str, &str); 5] = [
+    ("items", "category_id", "categories"),
+    ("items", "location_id", "locations"),
+    ("pictures", "item_id", "items"),
+    ("locations", "parent_id", "locations"),
+    ("categories", "parent_id", "categories"),
+];
+
+/// Row pointing at a row that does not exist
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DanglingReference {
+    pub table: String,
+    pub column: String,
+    pub id: i32,
+    pub missing_id: i32,
+}
+
+/// Problems found by comparing the tables with each other and with file storage
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IntegrityReport {
+    pub dangling_references: Vec<DanglingReference>,
+    /// Whether file storage was compared with the picture rows, it is skipped when not configured
+    pub storage_checked: bool,
+    pub missing_objects: Vec<MissingObject>,
+    pub orphan_objects: Vec<OrphanObject>,
+}
+
+impl IntegrityReport {
+    /// Looks for inconsistencies without changing anything. File storage is only compared with
+    /// the picture rows when `storage` is given
+    pub async fn check(pool: &PgPool, storage: Option<&S3Permits>) -> Result<IntegrityReport> {
+        let dangling_references = Self::dangling_references(pool).await?;
+        let (missing_objects, orphan_objects) = if let Some(permits) = storage {
+            PictureInfo::storage_mismatches(pool, permits).await?
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        Ok(IntegrityReport {
+            dangling_references,
+            storage_checked: storage.is_some(),
+            missing_objects,
+            orphan_objects,
+        })
+    }
+
+    async fn dangling_references(pool: &PgPool) -> Result<Vec<DanglingReference>> {
+        let mut dangling = Vec::new();
+        for (table, column, referenced) in FOREIGN_KEYS {
+            let query = format!(
+                "SELECT '{table}' AS \"table\", '{column}' AS \"column\", t.id, t.{column} AS missing_id FROM {table} t LEFT JOIN {referenced} r ON r.id = t.{column} WHERE t.{column} IS NOT NULL AND r.id IS NULL ORDER BY t.id"
+            );
+            let rows = sqlx::query_as::<_, DanglingReference>(&query)
+                .fetch_all(pool)
+                .await?;
+            dangling.extend(rows);
+        }
+        Ok(dangling)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::{Item, NewItem};
+
+    use super::*;
+    use chrono::Utc;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn reports_dangling_category(pool: PgPool) {
+        let item_id =
+            Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+                .await
+                .unwrap();
+        let report = IntegrityReport::check(&pool, None).await.unwrap();
+        assert!(report.dangling_references.is_empty());
+
+        // A manual edit with the foreign key triggers switched off
+        let mut connection = pool.acquire().await.unwrap();
+        sqlx::query("SET session_replication_role = replica")
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE items SET category_id = 9999 WHERE id = $1")
+            .bind(item_id)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        sqlx::query("SET session_replication_role = DEFAULT")
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        drop(connection);
+
+        let report = IntegrityReport::check(&pool, None).await.unwrap();
+        assert!(!report.storage_checked);
+        assert_eq!(
+            report.dangling_references,
+            vec![DanglingReference {
+                table: "items".to_string(),
+                column: "category_id".to_string(),
+                id: item_id,
+                missing_id: 9999,
+            }]
+        );
+    }
+}
@@ -1,31 +1,283 @@
-use anyhow::Result;
+use std::io::Cursor;
+
+use anyhow::{bail, Result};
 use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use image::{DynamicImage, ImageFormat, Luma};
+use qrcode::QrCode;
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, PgPool};
+use serde_json::Value;
+use sqlx::{prelude::FromRow, Acquire, PgExecutor, PgPool, Postgres};
+
+use crate::picture::{PictureInfo, PictureUrl};
 
 #[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
 pub struct Item {
     pub id: i32,
-    name: String,
-    description: String,
+    pub name: String,
+    pub description: String,
     date_origin: DateTime<Utc>,
+    pub category_id: Option<i32>,
+    pub location_id: Option<i32>,
+    pub external_ref: Option<String>,
+    pub value_cents: Option<i64>,
+    /// Position in listings, assigned in insertion order and changed by swapping, ignored in
+    /// request bodies
+    #[serde(default)]
+    pub sort_order: i32,
+    /// Set by the database when the row is inserted, ignored in request bodies
+    #[serde(default)]
+    pub created_at: DateTime<Utc>,
+    /// Set by the database whenever the row changes, ignored in request bodies
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NewItem {
     pub name: String,
     pub description: String,
-    pub date_origin: DateTime<Utc>,
+    /// When left out the item is dated at the time it is stored
+    pub date_origin: Option<DateTime<Utc>>,
+    pub category_id: Option<i32>,
+    pub location_id: Option<i32>,
+    pub external_ref: Option<String>,
+    pub value_cents: Option<i64>,
+}
+
+impl NewItem {
+    /// Creates a new [`NewItem`] without category, location, external reference or value
+    #[cfg(test)]
+    pub fn new(name: &str, description: &str, date_origin: DateTime<Utc>) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            date_origin: Some(date_origin),
+            category_id: None,
+            location_id: None,
+            external_ref: None,
+            value_cents: None,
+        }
+    }
+}
+
+/// Largest number of items returned by [`Item::top_by_value`]
+pub const MAX_TOP_VALUE_LIMIT: i64 = 100;
+
+/// Largest number of items returned by [`Item::most_photographed`]
+pub const MAX_MOST_PHOTOGRAPHED_LIMIT: i64 = 100;
+
+/// Largest number of items returned by [`Item::similar`]
+pub const MAX_SIMILAR_LIMIT: i64 = 50;
+
+/// Keeps items worth at least `$1` and at most `$2` cents, a missing bound leaving that side open.
+/// Items without a value only pass when neither bound is given
+const VALUE_RANGE_FILTER: &str =
+    "($1::bigint IS NULL OR i.value_cents >= $1) AND ($2::bigint IS NULL OR i.value_cents <= $2)";
+
+/// Prefix of the codes printed on item labels, followed by the item id
+pub const SCAN_CODE_PREFIX: &str = "items://";
+
+/// Reads the item id from a scanned label code, either `items://<id>` or a bare id
+pub fn parse_scan_code(code: &str) -> Option<i32> {
+    let code = code.trim();
+    code.strip_prefix(SCAN_CODE_PREFIX)
+        .unwrap_or(code)
+        .parse()
+        .ok()
+}
+
+/// Renders a QR code for an item label as a PNG at least `size` pixels wide
+pub fn qr_code_png(item_id: i32, size: u32) -> Result<Vec<u8>> {
+    let code = QrCode::new(format!("{}{}", SCAN_CODE_PREFIX, item_id))?;
+    let image = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Columns of `items` that can be selected through field projection
+pub const PROJECTABLE_FIELDS: &[&str] = &[
+    "id",
+    "name",
+    "description",
+    "date_origin",
+    "category_id",
+    "location_id",
+    "external_ref",
+    "value_cents",
+    "sort_order",
+    "created_at",
+    "updated_at",
+];
+
+/// Number of distinct categories a set of items spans
+#[derive(FromRow, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CategoryCoverage {
+    pub distinct_categories: i64,
+}
+
+/// Item with the name of its category
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct ItemWithCategory {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub item: Item,
+    pub category: Option<String>,
+}
+
+/// Self-contained description of an item, suitable for sharing
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct ItemCard {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub item: Item,
+    pub category: Option<String>,
+    pub location: Option<String>,
+    #[sqlx(skip)]
+    pub pictures: Vec<PictureUrl>,
+}
+
+/// Item annotated with how many pictures it has
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct ItemWithPictureCount {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub item: Item,
+    pub picture_count: i64,
+}
+
+/// Item with a link to its first picture, if it has any
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ItemWithThumbnail {
+    #[serde(flatten)]
+    pub item: Item,
+    pub thumbnail_url: Option<String>,
+}
+
+/// Item joined with where its first picture is stored
+#[derive(FromRow)]
+struct ItemThumbnailRow {
+    #[sqlx(flatten)]
+    item: Item,
+    thumbnail_bucket: Option<String>,
+    thumbnail_hash: Option<String>,
+}
+
+/// Number of items acquired in a month, formatted as `YYYY-MM`
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct MonthCount {
+    pub month: String,
+    pub count: i64,
 }
 
 impl Item {
-    pub async fn read_from_db(pool: &PgPool) -> Result<Vec<Item>> {
-        let items = sqlx::query_as::<_, Item>("SELECT * FROM items")
-            .fetch_all(pool)
+    pub async fn read_from_db(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<Item>> {
+        let items = sqlx::query_as::<_, Item>(
+            "SELECT * FROM items ORDER BY sort_order, id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Streams all items in id order without loading them all into memory
+    pub fn stream_from_db(pool: &PgPool) -> BoxStream<'_, Result<Item, sqlx::Error>> {
+        sqlx::query_as::<_, Item>("SELECT * FROM items ORDER BY id").fetch(pool)
+    }
+
+    /// Reads items as JSON objects holding only the given [`PROJECTABLE_FIELDS`]
+    pub async fn read_projected_from_db(
+        pool: &PgPool,
+        fields: &[&str],
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Value>> {
+        if let Some(field) = fields
+            .iter()
+            .find(|field| !PROJECTABLE_FIELDS.contains(field))
+        {
+            bail!("Unknown field `{}`", field);
+        }
+        let items = sqlx::query_scalar::<_, Value>(&format!(
+            "SELECT to_jsonb(i) FROM (SELECT {} FROM items ORDER BY sort_order, id LIMIT $1 OFFSET $2) i",
+            fields.join(", ")
+        ))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    pub async fn count(pool: &PgPool) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM items")
+            .fetch_one(pool)
             .await?;
+        Ok(count)
+    }
+
+    /// Reads items worth at least `min_value` and at most `max_value` cents, leaving out items
+    /// without a value
+    pub async fn read_in_value_range(
+        pool: &PgPool,
+        min_value: Option<i64>,
+        max_value: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Item>> {
+        let items = sqlx::query_as::<_, Item>(&format!(
+            "SELECT * FROM items i WHERE {} ORDER BY i.sort_order, i.id LIMIT $3 OFFSET $4",
+            VALUE_RANGE_FILTER
+        ))
+        .bind(min_value)
+        .bind(max_value)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    pub async fn count_in_value_range(
+        pool: &PgPool,
+        min_value: Option<i64>,
+        max_value: Option<i64>,
+    ) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM items i WHERE {}",
+            VALUE_RANGE_FILTER
+        ))
+        .bind(min_value)
+        .bind(max_value)
+        .fetch_one(pool)
+        .await?;
+        Ok(count)
+    }
+
+    /// Reads items that have not been updated since they were created
+    pub async fn read_untouched(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<Item>> {
+        let items = sqlx::query_as::<_, Item>(
+            "SELECT * FROM items i WHERE i.updated_at = i.created_at ORDER BY i.id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
         Ok(items)
     }
 
+    pub async fn count_untouched(pool: &PgPool) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM items i WHERE i.updated_at = i.created_at",
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(count)
+    }
+
     pub async fn read_from_db_by_id(pool: &PgPool, id: i32) -> Result<Item> {
         let item = sqlx::query_as::<_, Item>("SELECT * FROM items i WHERE i.id = $1")
             .bind(id)
@@ -34,55 +286,266 @@ impl Item {
         Ok(item)
     }
 
-    pub async fn insert_into_db(
-        pool: &PgPool,
-        name: &str,
-        description: &str,
-        date_origin: DateTime<Utc>,
-    ) -> Result<()> {
-        sqlx::query("INSERT INTO items (name, description, date_origin) VALUES ($1, $2, $3)")
-            .bind(name)
-            .bind(description)
-            .bind(date_origin)
-            .execute(pool)
+    /// Reads the item carrying the given reference from an external system
+    pub async fn read_by_external_ref(pool: &PgPool, external_ref: &str) -> Result<Item> {
+        let item = sqlx::query_as::<_, Item>("SELECT * FROM items i WHERE i.external_ref = $1")
+            .bind(external_ref)
+            .fetch_one(pool)
             .await?;
-        Ok(())
+        Ok(item)
+    }
+
+    /// Reads an item with its category and location names and presigned links to its pictures
+    pub async fn read_card(pool: &PgPool, id: i32, expiry_secs: u32) -> Result<ItemCard> {
+        let mut card = sqlx::query_as::<_, ItemCard>(
+            "SELECT i.*, c.name AS category, l.name AS location FROM items i LEFT JOIN categories c ON c.id = i.category_id LEFT JOIN locations l ON l.id = i.location_id WHERE i.id = $1",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+        card.pictures = PictureInfo::presigned_urls_for_item(pool, id, expiry_secs).await?;
+        Ok(card)
+    }
+
+    /// Reads items with presigned links to their first pictures, valid for `expiry_secs`
+    pub async fn read_with_thumbnails(
+        pool: &PgPool,
+        limit: i64,
+        offset: i64,
+        expiry_secs: u32,
+    ) -> Result<Vec<ItemWithThumbnail>> {
+        let rows = sqlx::query_as::<_, ItemThumbnailRow>(
+            "SELECT i.*, p.object_storage_location AS thumbnail_bucket, p.hash AS thumbnail_hash FROM items i LEFT JOIN LATERAL (SELECT * FROM pictures WHERE item_id = i.id ORDER BY position, id LIMIT 1) p ON true ORDER BY i.sort_order, i.id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        let mut items = Vec::with_capacity(rows.len());
+        for row in rows {
+            let thumbnail_url = match (row.thumbnail_bucket, row.thumbnail_hash) {
+                (Some(bucket), Some(hash)) => {
+                    Some(PictureInfo::presigned_url(&bucket, &hash, expiry_secs).await?)
+                }
+                _ => None,
+            };
+            items.push(ItemWithThumbnail {
+                item: row.item,
+                thumbnail_url,
+            });
+        }
+        Ok(items)
+    }
+
+    /// Reads items together with the number of pictures of each, as cached on the item row
+    pub async fn read_with_picture_counts(
+        pool: &PgPool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ItemWithPictureCount>> {
+        let items = sqlx::query_as::<_, ItemWithPictureCount>(
+            "SELECT i.* FROM items i ORDER BY i.sort_order, i.id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Reads the `limit` most valuable items, skipping items without a value
+    pub async fn top_by_value(pool: &PgPool, limit: i64) -> Result<Vec<Item>> {
+        let items = sqlx::query_as::<_, Item>(
+            "SELECT * FROM items i WHERE i.value_cents IS NOT NULL ORDER BY i.value_cents DESC, i.id LIMIT $1",
+        )
+        .bind(limit.clamp(0, MAX_TOP_VALUE_LIMIT))
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Reads up to `limit` items resembling an item, best match first. Sharing the category scores
+    /// 1 and the trigram similarity of the names adds up to 1 more, items scoring 0 are left out
+    pub async fn similar(pool: &PgPool, id: i32, limit: i64) -> Result<Vec<Item>> {
+        let items = sqlx::query_as::<_, Item>(
+            "SELECT s.* FROM (SELECT o.*, (CASE WHEN o.category_id = i.category_id THEN 1 ELSE 0 END) + similarity(o.name, i.name) AS score FROM items i JOIN items o ON o.id <> i.id WHERE i.id = $1) s WHERE s.score > 0 ORDER BY s.score DESC, s.id LIMIT $2",
+        )
+        .bind(id)
+        .bind(limit.clamp(0, MAX_SIMILAR_LIMIT))
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Counts the distinct categories of the items worth between `min_value` and `max_value`
+    /// cents, filtered like [`Item::read_in_value_range`] and leaving out uncategorized items
+    pub async fn category_coverage(
+        pool: &PgPool,
+        min_value: Option<i64>,
+        max_value: Option<i64>,
+    ) -> Result<CategoryCoverage> {
+        let coverage = sqlx::query_as::<_, CategoryCoverage>(&format!(
+            "SELECT COUNT(DISTINCT i.category_id) AS distinct_categories FROM items i WHERE {}",
+            VALUE_RANGE_FILTER
+        ))
+        .bind(min_value)
+        .bind(max_value)
+        .fetch_one(pool)
+        .await?;
+        Ok(coverage)
+    }
+
+    /// Counts items per month of `date_origin`, truncated in UTC
+    pub async fn counts_by_month(pool: &PgPool) -> Result<Vec<MonthCount>> {
+        let counts = sqlx::query_as::<_, MonthCount>(
+            "SELECT to_char(date_trunc('month', date_origin AT TIME ZONE 'UTC'), 'YYYY-MM') AS month, COUNT(*) AS count FROM items GROUP BY month ORDER BY month",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(counts)
+    }
+
+    pub async fn insert_into_db(executor: impl PgExecutor<'_>, item: &NewItem) -> Result<i32> {
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO items (name, description, date_origin, category_id, location_id, external_ref, value_cents) VALUES ($1, $2, COALESCE($3, now()), $4, $5, $6, $7) RETURNING id",
+        )
+        .bind(&item.name)
+        .bind(&item.description)
+        .bind(item.date_origin)
+        .bind(item.category_id)
+        .bind(item.location_id)
+        .bind(&item.external_ref)
+        .bind(item.value_cents)
+        .fetch_one(executor)
+        .await?;
+        Ok(id)
+    }
+
+    /// Reads the `limit` items with the most pictures, skipping items without pictures
+    pub async fn most_photographed(pool: &PgPool, limit: i64) -> Result<Vec<ItemWithPictureCount>> {
+        let items = sqlx::query_as::<_, ItemWithPictureCount>(
+            "SELECT i.* FROM items i WHERE i.picture_count > 0 ORDER BY i.picture_count DESC, i.id LIMIT $1",
+        )
+        .bind(limit.clamp(0, MAX_MOST_PHOTOGRAPHED_LIMIT))
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Recounts the pictures of an item, correcting its cached count, and returns the count
+    pub async fn recount_pictures(pool: &PgPool, id: i32) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "UPDATE items i SET picture_count = (SELECT COUNT(*) FROM pictures p WHERE p.item_id = i.id) WHERE i.id = $1 RETURNING i.picture_count",
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+        Ok(count)
     }
 
-    pub async fn delete_from_db(pool: &PgPool, id: i32) -> Result<()> {
-        sqlx::query("DELETE FROM items i WHERE i.id = $1")
+    /// Recounts the pictures of every item, returning how many cached counts were wrong
+    pub async fn recount_all_pictures(pool: &PgPool) -> Result<u64> {
+        let corrected = sqlx::query(
+            "UPDATE items i SET picture_count = c.count FROM (SELECT i.id, COUNT(p.id) AS count FROM items i LEFT JOIN pictures p ON p.item_id = i.id GROUP BY i.id) c WHERE c.id = i.id AND i.picture_count <> c.count",
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+        Ok(corrected)
+    }
+
+    /// Deletes an item by id, returning whether there was one to delete
+    pub async fn delete_from_db(executor: impl PgExecutor<'_>, id: i32) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM items i WHERE i.id = $1")
             .bind(id)
-            .execute(pool)
+            .execute(executor)
             .await?;
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
-    pub async fn update_in_db(pool: &PgPool, item: &Item) -> Result<()> {
-        sqlx::query("UPDATE items SET name = $1, description = $2, date_origin = $3 WHERE id = $4")
-            .bind(&item.name)
-            .bind(&item.description)
-            .bind(item.date_origin)
-            .bind(item.id)
-            .execute(pool)
-            .await?;
+    /// Picks an item at random, only among the items of `category_id` when given
+    pub async fn random(pool: &PgPool, category_id: Option<i32>) -> Result<Item> {
+        let item = sqlx::query_as::<_, Item>(
+            "SELECT * FROM items i WHERE $1::INTEGER IS NULL OR i.category_id = $1 ORDER BY random() LIMIT 1",
+        )
+        .bind(category_id)
+        .fetch_one(pool)
+        .await?;
+        Ok(item)
+    }
+
+    /// Exchanges the listing positions of two items in one transaction
+    pub async fn swap_sort_order(
+        connection: impl Acquire<'_, Database = Postgres>,
+        first: i32,
+        second: i32,
+    ) -> Result<()> {
+        let mut transaction = connection.begin().await?;
+        let orders = sqlx::query_as::<_, (i32, i32)>(
+            "SELECT i.id, i.sort_order FROM items i WHERE i.id IN ($1, $2) ORDER BY i.id FOR UPDATE",
+        )
+        .bind(first)
+        .bind(second)
+        .fetch_all(&mut *transaction)
+        .await?;
+        if orders.len() != 2 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        sqlx::query(
+            "UPDATE items SET sort_order = CASE WHEN id = $1 THEN $4 ELSE $2 END WHERE id IN ($1, $3)",
+        )
+        .bind(orders[0].0)
+        .bind(orders[0].1)
+        .bind(orders[1].0)
+        .bind(orders[1].1)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
         Ok(())
     }
+
+    /// Updates an item by id, unless it changed in a later second than `unmodified_since`.
+    /// Returns whether a row was updated
+    pub async fn update_in_db(
+        executor: impl PgExecutor<'_>,
+        item: &Item,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE items SET name = $1, description = $2, date_origin = $3, category_id = $4, location_id = $5, external_ref = $6, value_cents = $7, updated_at = now() WHERE id = $8 AND ($9::timestamptz IS NULL OR date_trunc('second', updated_at) <= $9)",
+        )
+        .bind(&item.name)
+        .bind(&item.description)
+        .bind(item.date_origin)
+        .bind(item.category_id)
+        .bind(item.location_id)
+        .bind(&item.external_ref)
+        .bind(item.value_cents)
+        .bind(item.id)
+        .bind(unmodified_since)
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::{category::Category, location::Location};
+    use chrono::TimeZone;
     use sqlx::PgPool;
 
     #[sqlx::test]
     pub async fn create(pool: PgPool) {
         let now = Utc::now();
-        Item::insert_into_db(&pool, "Hei", "Test", now)
+        Item::insert_into_db(&pool, &NewItem::new("Hei", "Test", now))
             .await
             .unwrap();
 
-        let items = Item::read_from_db(&pool).await;
+        let items = Item::read_from_db(&pool, 100, 0).await;
 
         assert!(items.is_ok());
         let items = items.unwrap();
@@ -96,7 +559,7 @@ mod tests {
     #[sqlx::test]
     pub async fn select_by_id(pool: PgPool) {
         let now = Utc::now();
-        Item::insert_into_db(&pool, "Hei", "Test", now)
+        Item::insert_into_db(&pool, &NewItem::new("Hei", "Test", now))
             .await
             .unwrap();
 
@@ -115,7 +578,7 @@ mod tests {
     #[sqlx::test]
     pub async fn delete(pool: PgPool) {
         let now = Utc::now();
-        Item::insert_into_db(&pool, "Hei", "Test", now)
+        Item::insert_into_db(&pool, &NewItem::new("Hei", "Test", now))
             .await
             .unwrap();
 
@@ -132,7 +595,8 @@ mod tests {
 
         let res = Item::delete_from_db(&pool, item.id).await;
 
-        assert!(res.is_ok());
+        assert!(res.unwrap());
+        assert!(!Item::delete_from_db(&pool, item.id).await.unwrap());
 
         let item = Item::read_from_db_by_id(&pool, 1).await;
 
@@ -144,7 +608,7 @@ mod tests {
     #[sqlx::test]
     pub async fn update(pool: PgPool) {
         let now = Utc::now();
-        Item::insert_into_db(&pool, "Hei", "Test", now)
+        Item::insert_into_db(&pool, &NewItem::new("Hei", "Test", now))
             .await
             .unwrap();
 
@@ -161,7 +625,7 @@ mod tests {
 
         item.name = "Hallo".to_string();
 
-        let res = Item::update_in_db(&pool, &item).await;
+        let res = Item::update_in_db(&pool, &item, None).await;
 
         dbg!(&res);
 
@@ -178,4 +642,352 @@ mod tests {
         assert_eq!(item2.description, "Test".to_string());
         assert!((item2.date_origin - now).num_seconds() < 1);
     }
+
+    #[sqlx::test]
+    pub async fn counts_by_month(pool: PgPool) {
+        let dates = [
+            Utc.with_ymd_and_hms(2024, 1, 3, 12, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 1, 31, 23, 30, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap(),
+        ];
+        for date in dates {
+            Item::insert_into_db(&pool, &NewItem::new("Hei", "Test", date))
+                .await
+                .unwrap();
+        }
+
+        let counts = Item::counts_by_month(&pool).await.unwrap();
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0].month, "2024-01".to_string());
+        assert_eq!(counts[0].count, 2);
+        assert_eq!(counts[1].month, "2024-03".to_string());
+        assert_eq!(counts[1].count, 1);
+    }
+
+    #[sqlx::test]
+    pub async fn read_card(pool: PgPool) {
+        Category::insert_into_db(&pool, "Furniture", "Things to sit on", None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(1),
+                location_id: Some(1),
+                ..NewItem::new("Stol", "Test", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let card = Item::read_card(&pool, 1, 60).await.unwrap();
+
+        assert_eq!(card.item.id, 1);
+        assert_eq!(card.category, Some("Furniture".to_string()));
+        assert_eq!(card.location, Some("Kitchen".to_string()));
+        assert!(card.pictures.is_empty());
+    }
+
+    #[sqlx::test]
+    pub async fn read_by_external_ref(pool: PgPool) {
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                external_ref: Some("SKU-42".to_string()),
+                ..NewItem::new("Stol", "Test", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let item = Item::read_by_external_ref(&pool, "SKU-42").await.unwrap();
+
+        assert_eq!(item.name, "Stol".to_string());
+        assert_eq!(item.external_ref, Some("SKU-42".to_string()));
+        assert!(Item::read_by_external_ref(&pool, "SKU-43").await.is_err());
+    }
+
+    #[test]
+    pub fn qr_code_is_png() {
+        let png = qr_code_png(1, 128).unwrap();
+
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    pub fn parse_scan_codes() {
+        assert_eq!(parse_scan_code("items://42"), Some(42));
+        assert_eq!(parse_scan_code(" 42 "), Some(42));
+        assert_eq!(parse_scan_code("items://"), None);
+        assert_eq!(parse_scan_code("shop://42"), None);
+    }
+
+    #[sqlx::test]
+    pub async fn read_with_picture_counts(pool: PgPool) {
+        for name in ["Stol", "Bord"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        for (position, hash) in [(1, "a"), (2, "b")] {
+            sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location, position) VALUES (1, 'Bilde', $1, 'item-1', $2)")
+                .bind(hash)
+                .bind(position)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        Item::recount_all_pictures(&pool).await.unwrap();
+
+        let items = Item::read_with_picture_counts(&pool, 100, 0).await.unwrap();
+
+        let counts = items
+            .iter()
+            .map(|i| (i.item.id, i.picture_count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![(1, 2), (2, 0)]);
+    }
+
+    #[sqlx::test]
+    pub async fn read_in_id_order(pool: PgPool) {
+        for name in ["Stol", "Bord", "Lampe", "Sofa", "Hylle"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE items SET description = 'Flyttet' WHERE id = 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            let items = Item::read_from_db(&pool, 100, 0).await.unwrap();
+            let ids = items.iter().map(|i| i.id).collect::<Vec<_>>();
+            assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+        }
+    }
+
+    #[sqlx::test]
+    pub async fn top_by_value(pool: PgPool) {
+        for (name, value_cents) in [
+            ("Stol", Some(500)),
+            ("Bord", None),
+            ("Sofa", Some(90000)),
+            ("Lampe", Some(2500)),
+        ] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    value_cents,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let items = Item::top_by_value(&pool, 2).await.unwrap();
+
+        let values = items.iter().map(|i| i.value_cents).collect::<Vec<_>>();
+        assert_eq!(values, vec![Some(90000), Some(2500)]);
+    }
+
+    #[sqlx::test]
+    pub async fn read_untouched(pool: PgPool) {
+        let chair = Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+            .await
+            .unwrap();
+        let table = Item::insert_into_db(&pool, &NewItem::new("Bord", "Test", Utc::now()))
+            .await
+            .unwrap();
+        let mut item = Item::read_from_db_by_id(&pool, chair).await.unwrap();
+        item.description = "Noe å sitte på".to_string();
+        Item::update_in_db(&pool, &item, None).await.unwrap();
+
+        let items = Item::read_untouched(&pool, 100, 0).await.unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, table);
+        assert_eq!(Item::count_untouched(&pool).await.unwrap(), 1);
+    }
+
+    #[sqlx::test]
+    pub async fn category_coverage(pool: PgPool) {
+        let furniture = Category::insert_into_db(&pool, "Furniture", "Things to sit on", None)
+            .await
+            .unwrap();
+        let tools = Category::insert_into_db(&pool, "Tools", "Things to fix with", None)
+            .await
+            .unwrap();
+        for (name, category_id, value_cents) in [
+            ("Stol", Some(furniture), Some(500)),
+            ("Bord", Some(furniture), None),
+            ("Hammer", Some(tools), Some(2500)),
+            ("Sykkel", None, Some(90000)),
+        ] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    category_id,
+                    value_cents,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let coverage = Item::category_coverage(&pool, None, None).await.unwrap();
+        assert_eq!(coverage.distinct_categories, 2);
+
+        let coverage = Item::category_coverage(&pool, None, Some(1000))
+            .await
+            .unwrap();
+        assert_eq!(coverage.distinct_categories, 1);
+
+        let coverage = Item::category_coverage(&pool, Some(5000), None)
+            .await
+            .unwrap();
+        assert_eq!(coverage.distinct_categories, 0);
+    }
+
+    #[sqlx::test]
+    pub async fn recount_pictures(pool: PgPool) {
+        for name in ["Stol", "Bord"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location, position) VALUES (1, 'Bilde', 'a', 'pictures', 1)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE items SET picture_count = 7")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(Item::recount_pictures(&pool, 1).await.unwrap(), 1);
+        assert_eq!(Item::recount_all_pictures(&pool).await.unwrap(), 1);
+        assert_eq!(Item::recount_all_pictures(&pool).await.unwrap(), 0);
+
+        let counts = Item::read_with_picture_counts(&pool, 100, 0)
+            .await
+            .unwrap()
+            .iter()
+            .map(|i| i.picture_count)
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![1, 0]);
+    }
+
+    #[sqlx::test]
+    pub async fn most_photographed(pool: PgPool) {
+        for name in ["Stol", "Bord", "Lampe"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        for (item_id, position, hash) in [(2, 1, "a"), (3, 1, "b"), (3, 2, "c"), (3, 3, "d")] {
+            sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location, position) VALUES ($1, 'Bilde', $2, 'pictures', $3)")
+                .bind(item_id)
+                .bind(hash)
+                .bind(position)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        Item::recount_all_pictures(&pool).await.unwrap();
+
+        let items = Item::most_photographed(&pool, 10).await.unwrap();
+
+        let counts = items
+            .iter()
+            .map(|i| (i.item.name.as_str(), i.picture_count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![("Lampe", 3), ("Bord", 1)]);
+    }
+
+    #[sqlx::test]
+    pub async fn read_in_value_range(pool: PgPool) {
+        for (name, value_cents) in [
+            ("Stol", Some(500)),
+            ("Bord", None),
+            ("Sofa", Some(90000)),
+            ("Lampe", Some(2500)),
+        ] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    value_cents,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let names = |items: Vec<Item>| items.into_iter().map(|i| i.name).collect::<Vec<_>>();
+
+        let items = Item::read_in_value_range(&pool, Some(500), Some(2500), 100, 0)
+            .await
+            .unwrap();
+        assert_eq!(names(items), vec!["Stol", "Lampe"]);
+
+        let items = Item::read_in_value_range(&pool, Some(1000), None, 100, 0)
+            .await
+            .unwrap();
+        assert_eq!(names(items), vec!["Sofa", "Lampe"]);
+        assert_eq!(
+            Item::count_in_value_range(&pool, None, Some(1000))
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[sqlx::test]
+    pub async fn similar(pool: PgPool) {
+        let furniture = Category::insert_into_db(&pool, "Furniture", "Things to sit on", None)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for (name, category_id) in [
+            ("Stol", Some(furniture)),
+            ("Sykkel", None),
+            ("Bord", Some(furniture)),
+            ("Lenestol", Some(furniture)),
+        ] {
+            let id = Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    category_id,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+
+        let items = Item::similar(&pool, ids[0], 10).await.unwrap();
+
+        let names = items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Lenestol", "Bord", "Sykkel"]);
+    }
+
+    #[sqlx::test]
+    pub async fn read_with_thumbnails_without_pictures(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+            .await
+            .unwrap();
+
+        let items = Item::read_with_thumbnails(&pool, 100, 0, 60).await.unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert!(items[0].thumbnail_url.is_none());
+    }
 }
@@ -1,36 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{Acquire, FromRow, PgConnection, PgExecutor, PgPool, Postgres};
+
+use crate::{
+    item::{Item, ItemWithCategory},
+    upsert::Upserted,
+};
 
 #[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
 pub struct Location {
     pub id: i32,
     pub name: String,
     pub description: String,
+    pub parent_id: Option<i32>,
+    pub capacity: Option<i32>,
+    /// Set by the database whenever the row changes, ignored in request bodies
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NewLocation {
     pub name: String,
     pub description: String,
+    pub parent_id: Option<i32>,
+    pub capacity: Option<i32>,
+}
+
+/// How much of a location's capacity is taken up by items, each item counting as one unit
+#[derive(FromRow, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Occupancy {
+    pub capacity: Option<i32>,
+    pub used: i64,
+    pub remaining: Option<i64>,
+}
+
+/// Outcome of [`Location::receive_items`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Received {
+    /// Ids of the items moved in, items already in the location are left out
+    Moved(Vec<i32>),
+    /// Nothing was moved because the location only has room for `remaining` of the `arriving`
+    /// items
+    Full { remaining: i64, arriving: i64 },
+}
+
+/// Number of items kept directly in a location
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct LocationItemCount {
+    pub location_id: i32,
+    pub name: String,
+    pub item_count: i64,
+}
+
+/// Location with the last time one of its items arrived or changed
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct RecentlyActiveLocation {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub location: Location,
+    pub last_active_at: DateTime<Utc>,
+}
+
+/// Largest number of locations returned by [`Location::recently_active`]
+pub const MAX_RECENTLY_ACTIVE_LIMIT: i64 = 100;
+
+/// Reads the [`Occupancy`] of location `$1`
+const OCCUPANCY_QUERY: &str = "SELECT l.capacity, COUNT(i.id) AS used, l.capacity - COUNT(i.id) AS remaining FROM locations l LEFT JOIN items i ON i.location_id = l.id WHERE l.id = $1 GROUP BY l.id";
+
+/// Selects the ids of location `$1` and all locations nested below it as `subtree`
+const SUBTREE_CTE: &str = "WITH RECURSIVE subtree AS (SELECT id FROM locations WHERE id = $1 UNION SELECT l.id FROM locations l JOIN subtree s ON l.parent_id = s.id)";
+
+/// Location with the locations nested below it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LocationNode {
+    pub location: Location,
+    pub children: Vec<LocationNode>,
+}
+
+impl LocationNode {
+    /// Nests `location` and its descendants, taking children from `by_parent`
+    fn assemble(location: Location, by_parent: &mut HashMap<i32, Vec<Location>>) -> LocationNode {
+        let children = by_parent
+            .remove(&location.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|child| LocationNode::assemble(child, by_parent))
+            .collect();
+        LocationNode { location, children }
+    }
+}
+
+/// Location subtree together with every item kept anywhere in it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LocationExport {
+    pub tree: LocationNode,
+    pub items: Vec<ItemWithCategory>,
 }
 
 impl NewLocation {
     /// Creates a new [`NewLocation`].
+    #[cfg(test)]
     pub fn new(name: String, description: String) -> Self {
-        Self { name, description }
+        Self {
+            name,
+            description,
+            parent_id: None,
+            capacity: None,
+        }
     }
 }
 
 impl Location {
     /// Reads all locations from database
-    pub async fn read_from_db(pool: &PgPool) -> Result<Vec<Location>> {
-        let locations = sqlx::query_as::<_, Location>("SELECT * FROM locations")
-            .fetch_all(pool)
-            .await?;
+    pub async fn read_from_db(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<Location>> {
+        let locations =
+            sqlx::query_as::<_, Location>("SELECT * FROM locations ORDER BY id LIMIT $1 OFFSET $2")
+                .bind(limit)
+                .bind(offset)
+                .fetch_all(pool)
+                .await?;
         Ok(locations)
     }
 
+    /// Counts all locations in the database
+    pub async fn count(pool: &PgPool) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM locations")
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
     /// Reads a location by id from database
     pub async fn read_from_db_by_id(pool: &PgPool, id: i32) -> Result<Location> {
         let location = sqlx::query_as::<_, Location>("SELECT * FROM locations l WHERE l.id = $1")
@@ -40,34 +143,261 @@ impl Location {
         Ok(location)
     }
 
+    /// Searches the locations nested below a location by name and description
+    pub async fn search_in_subtree(pool: &PgPool, id: i32, term: &str) -> Result<Vec<Location>> {
+        let pattern = format!(
+            "%{}%",
+            term.replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let locations = sqlx::query_as::<_, Location>(&format!(
+            "{} SELECT l.* FROM locations l JOIN subtree s ON l.id = s.id WHERE l.id <> $1 AND (l.name ILIKE $2 OR l.description ILIKE $2) ORDER BY l.id",
+            SUBTREE_CTE
+        ))
+        .bind(id)
+        .bind(pattern)
+        .fetch_all(pool)
+        .await?;
+        Ok(locations)
+    }
+
+    /// Reads a location and every location below it as a tree, children ordered by id
+    pub async fn read_tree(pool: &PgPool, id: i32) -> Result<LocationNode> {
+        let root = Location::read_from_db_by_id(pool, id).await?;
+        let descendants = sqlx::query_as::<_, Location>(&format!(
+            "{} SELECT l.* FROM locations l JOIN subtree s ON l.id = s.id WHERE l.id <> $1 ORDER BY l.id",
+            SUBTREE_CTE
+        ))
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+
+        let mut by_parent: HashMap<i32, Vec<Location>> = HashMap::new();
+        for location in descendants {
+            if let Some(parent_id) = location.parent_id {
+                by_parent.entry(parent_id).or_default().push(location);
+            }
+        }
+        Ok(LocationNode::assemble(root, &mut by_parent))
+    }
+
+    /// Reads a location subtree and all items in it, with their category names
+    pub async fn export(pool: &PgPool, id: i32) -> Result<LocationExport> {
+        let tree = Location::read_tree(pool, id).await?;
+        let items = sqlx::query_as::<_, ItemWithCategory>(&format!(
+            "{} SELECT i.*, c.name AS category FROM items i JOIN subtree s ON i.location_id = s.id LEFT JOIN categories c ON c.id = i.category_id ORDER BY i.id",
+            SUBTREE_CTE
+        ))
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+        Ok(LocationExport { tree, items })
+    }
+
+    /// Reads the items kept directly in a location, or anywhere below it when `recursive`
+    pub async fn read_items(pool: &PgPool, id: i32, recursive: bool) -> Result<Vec<Item>> {
+        let query = if recursive {
+            format!(
+                "{} SELECT i.* FROM items i JOIN subtree s ON i.location_id = s.id ORDER BY i.id",
+                SUBTREE_CTE
+            )
+        } else {
+            "SELECT * FROM items i WHERE i.location_id = $1 ORDER BY i.id".to_string()
+        };
+        let items = sqlx::query_as::<_, Item>(&query)
+            .bind(id)
+            .fetch_all(pool)
+            .await?;
+        Ok(items)
+    }
+
+    /// Counts the items in every location, fullest first, including empty locations
+    pub async fn item_counts(pool: &PgPool) -> Result<Vec<LocationItemCount>> {
+        let counts = sqlx::query_as::<_, LocationItemCount>(
+            "SELECT l.id AS location_id, l.name, COUNT(i.id) AS item_count FROM locations l LEFT JOIN items i ON i.location_id = l.id GROUP BY l.id ORDER BY item_count DESC, l.id",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(counts)
+    }
+
+    /// Reads the `limit` locations whose items most recently arrived, skipping empty locations.
+    /// Moves are not recorded on their own, so the last change to an item stands in for its arrival
+    pub async fn recently_active(pool: &PgPool, limit: i64) -> Result<Vec<RecentlyActiveLocation>> {
+        let locations = sqlx::query_as::<_, RecentlyActiveLocation>(
+            "SELECT l.*, MAX(i.updated_at) AS last_active_at FROM locations l JOIN items i ON i.location_id = l.id GROUP BY l.id ORDER BY last_active_at DESC, l.id LIMIT $1",
+        )
+        .bind(limit.clamp(0, MAX_RECENTLY_ACTIVE_LIMIT))
+        .fetch_all(pool)
+        .await?;
+        Ok(locations)
+    }
+
     /// Insert location into database
-    pub async fn insert_into_db(pool: &PgPool, name: &str, description: &str) -> Result<()> {
-        sqlx::query("INSERT INTO locations (name, description) VALUES ($1, $2)")
-            .bind(name)
-            .bind(description)
-            .execute(pool)
+    pub async fn insert_into_db(
+        executor: impl PgExecutor<'_>,
+        name: &str,
+        description: &str,
+        parent_id: Option<i32>,
+        capacity: Option<i32>,
+    ) -> Result<i32> {
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO locations (name, description, parent_id, capacity) VALUES ($1, $2, $3, $4) RETURNING id",
+        )
+        .bind(name)
+        .bind(description)
+        .bind(parent_id)
+        .bind(capacity)
+        .fetch_one(executor)
+        .await?;
+        Ok(id)
+    }
+
+    /// Inserts a location, or updates the description of the one with the same name
+    pub async fn upsert_by_name(
+        executor: impl PgExecutor<'_>,
+        name: &str,
+        description: &str,
+    ) -> Result<Upserted> {
+        let upserted = sqlx::query_as::<_, Upserted>(
+            "INSERT INTO locations (name, description) VALUES ($1, $2) ON CONFLICT (name) DO UPDATE SET description = EXCLUDED.description, updated_at = now() RETURNING id, (xmax = 0) AS created",
+        )
+        .bind(name)
+        .bind(description)
+        .fetch_one(executor)
+        .await?;
+        Ok(upserted)
+    }
+
+    /// Reads how much of a location's capacity is in use
+    pub async fn occupancy(pool: &PgPool, id: i32) -> Result<Occupancy> {
+        let occupancy = sqlx::query_as::<_, Occupancy>(OCCUPANCY_QUERY)
+            .bind(id)
+            .fetch_one(pool)
             .await?;
-        Ok(())
+        Ok(occupancy)
     }
 
-    /// Deletes a location from the database
-    pub async fn delete_from_db(pool: &PgPool, id: i32) -> Result<()> {
-        sqlx::query("DELETE FROM locations l WHERE l.id = $1")
+    /// Locks a location until the end of the transaction, failing as a missing row when there is
+    /// none. Writers placing items in the location wait for the lock
+    pub async fn lock(connection: &mut PgConnection, id: i32) -> Result<()> {
+        sqlx::query("SELECT l.id FROM locations l WHERE l.id = $1 FOR UPDATE")
             .bind(id)
-            .execute(pool)
+            .fetch_one(connection)
             .await?;
         Ok(())
     }
 
-    /// Updates a location by id in the database
-    pub async fn update_in_db(pool: &PgPool, location: &Location) -> Result<()> {
-        sqlx::query("UPDATE locations SET name = $1, description = $2 WHERE id = $3")
-            .bind(&location.name)
-            .bind(&location.description)
-            .bind(location.id)
-            .execute(pool)
+    /// Locks a location like [`Location::lock`] and reads its occupancy, so writers placing items
+    /// there take turns checking the capacity
+    pub async fn lock_occupancy(connection: &mut PgConnection, id: i32) -> Result<Occupancy> {
+        Self::lock(&mut *connection, id).await?;
+        let occupancy = sqlx::query_as::<_, Occupancy>(OCCUPANCY_QUERY)
+            .bind(id)
+            .fetch_one(connection)
             .await?;
-        Ok(())
+        Ok(occupancy)
+    }
+
+    /// Moves the given items into a location in one transaction. The location stays locked
+    /// while its capacity is checked, and unknown item ids fail the whole move as a missing row
+    pub async fn receive_items(
+        connection: impl Acquire<'_, Database = Postgres>,
+        id: i32,
+        item_ids: &[i32],
+    ) -> Result<Received> {
+        let mut transaction = connection.begin().await?;
+        let capacity = sqlx::query_scalar::<_, Option<i32>>(
+            "SELECT l.capacity FROM locations l WHERE l.id = $1 FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        let known = sqlx::query_scalar::<_, i32>("SELECT i.id FROM items i WHERE i.id = ANY($1)")
+            .bind(item_ids)
+            .fetch_all(&mut *transaction)
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let mut unknown = item_ids
+            .iter()
+            .filter(|item_id| !known.contains(item_id))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            unknown.sort_unstable();
+            unknown.dedup();
+            return Err(anyhow::Error::from(sqlx::Error::RowNotFound)
+                .context(format!("Unknown items {:?}", unknown)));
+        }
+
+        if let Some(capacity) = capacity {
+            let (used, arriving) = sqlx::query_as::<_, (i64, i64)>(
+                "SELECT COUNT(*) FILTER (WHERE i.location_id = $1), COUNT(*) FILTER (WHERE i.id = ANY($2) AND i.location_id IS DISTINCT FROM $1) FROM items i",
+            )
+            .bind(id)
+            .bind(item_ids)
+            .fetch_one(&mut *transaction)
+            .await?;
+            let remaining = i64::from(capacity) - used;
+            if arriving > remaining {
+                return Ok(Received::Full {
+                    remaining,
+                    arriving,
+                });
+            }
+        }
+
+        let moved = sqlx::query_scalar::<_, i32>(
+            "UPDATE items SET location_id = $1, updated_at = now() WHERE id = ANY($2) AND location_id IS DISTINCT FROM $1 RETURNING id",
+        )
+        .bind(id)
+        .bind(item_ids)
+        .fetch_all(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(Received::Moved(moved))
+    }
+
+    /// Counts the items kept directly in a location
+    pub async fn count_items(executor: impl PgExecutor<'_>, id: i32) -> Result<i64> {
+        let count =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM items i WHERE i.location_id = $1")
+                .bind(id)
+                .fetch_one(executor)
+                .await?;
+        Ok(count)
+    }
+
+    /// Deletes a location from the database, returning whether there was one to delete
+    pub async fn delete_from_db(executor: impl PgExecutor<'_>, id: i32) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM locations l WHERE l.id = $1")
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Updates a location by id in the database, unless it changed in a later second than
+    /// `unmodified_since`. Returns whether a row was updated
+    pub async fn update_in_db(
+        executor: impl PgExecutor<'_>,
+        location: &Location,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE locations SET name = $1, description = $2, parent_id = $3, capacity = $4, updated_at = now() WHERE id = $5 AND ($6::timestamptz IS NULL OR date_trunc('second', updated_at) <= $6)",
+        )
+        .bind(&location.name)
+        .bind(&location.description)
+        .bind(location.parent_id)
+        .bind(location.capacity)
+        .bind(location.id)
+        .bind(unmodified_since)
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected() > 0)
     }
 }
 
@@ -75,15 +405,17 @@ impl Location {
 mod tests {
 
     use super::*;
+    use crate::{category::Category, item::NewItem};
+    use chrono::Utc;
     use sqlx::PgPool;
 
     #[sqlx::test]
     pub async fn create(pool: PgPool) {
-        Location::insert_into_db(&pool, "Kitchen", "Where we make food")
+        Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
             .await
             .unwrap();
 
-        let locations = Location::read_from_db(&pool).await;
+        let locations = Location::read_from_db(&pool, 100, 0).await;
 
         assert!(locations.is_ok());
         let locations = locations.unwrap();
@@ -95,7 +427,7 @@ mod tests {
 
     #[sqlx::test]
     pub async fn select_by_id(pool: PgPool) {
-        Location::insert_into_db(&pool, "Kitchen", "Where we make food")
+        Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
             .await
             .unwrap();
 
@@ -110,7 +442,7 @@ mod tests {
 
     #[sqlx::test]
     pub async fn delete(pool: PgPool) {
-        Location::insert_into_db(&pool, "Kitchen", "Where we make food")
+        Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
             .await
             .unwrap();
 
@@ -124,7 +456,8 @@ mod tests {
 
         let res = Location::delete_from_db(&pool, location.id).await;
 
-        assert!(res.is_ok());
+        assert!(res.unwrap());
+        assert!(!Location::delete_from_db(&pool, location.id).await.unwrap());
 
         let location = Location::read_from_db_by_id(&pool, 1).await;
 
@@ -133,7 +466,7 @@ mod tests {
 
     #[sqlx::test]
     pub async fn update(pool: PgPool) {
-        Location::insert_into_db(&pool, "Kitchen", "Where we make food")
+        Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
             .await
             .unwrap();
 
@@ -146,7 +479,7 @@ mod tests {
         assert_eq!(location.description, "Where we make food".to_string());
 
         location.description = "Where I make food".to_string();
-        let res = Location::update_in_db(&pool, &location).await;
+        let res = Location::update_in_db(&pool, &location, None).await;
 
         assert!(res.is_ok());
 
@@ -154,4 +487,295 @@ mod tests {
         assert_eq!(location2.name, "Kitchen".to_string());
         assert_eq!(location2.description, "Where I make food".to_string());
     }
+
+    #[sqlx::test]
+    pub async fn upsert_by_name(pool: PgPool) {
+        let first = Location::upsert_by_name(&pool, "Kitchen", "Where we make food")
+            .await
+            .unwrap();
+        assert!(first.created);
+
+        let second = Location::upsert_by_name(&pool, "Kitchen", "Where I make food")
+            .await
+            .unwrap();
+        assert!(!second.created);
+        assert_eq!(first.id, second.id);
+
+        let locations = Location::read_from_db(&pool, 100, 0).await.unwrap();
+
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].name, "Kitchen".to_string());
+        assert_eq!(locations[0].description, "Where I make food".to_string());
+    }
+
+    #[sqlx::test]
+    pub async fn search_in_subtree(pool: PgPool) {
+        Location::insert_into_db(&pool, "Garage", "Where the car lives", None, None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Wall", "Back wall of the garage", Some(1), None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Garage shelf", "Tools", Some(2), None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Kitchen shelf", "Spices", Some(4), None)
+            .await
+            .unwrap();
+
+        let locations = Location::search_in_subtree(&pool, 1, "shelf")
+            .await
+            .unwrap();
+
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].name, "Garage shelf".to_string());
+        assert_eq!(locations[0].parent_id, Some(2));
+    }
+
+    #[sqlx::test]
+    pub async fn read_tree(pool: PgPool) {
+        let garage = Location::insert_into_db(&pool, "Garage", "Where the car lives", None, None)
+            .await
+            .unwrap();
+        let wall = Location::insert_into_db(&pool, "Wall", "Back wall", Some(garage), None)
+            .await
+            .unwrap();
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Tools", Some(wall), None)
+            .await
+            .unwrap();
+
+        let tree = Location::read_tree(&pool, garage).await.unwrap();
+
+        assert_eq!(tree.location.id, garage);
+        assert_eq!(tree.children.len(), 1);
+        assert_eq!(tree.children[0].location.id, wall);
+        assert_eq!(tree.children[0].children.len(), 1);
+        assert_eq!(tree.children[0].children[0].location.id, shelf);
+        assert!(tree.children[0].children[0].children.is_empty());
+
+        let leaf = Location::read_tree(&pool, shelf).await.unwrap();
+        assert_eq!(leaf.location.id, shelf);
+        assert!(leaf.children.is_empty());
+    }
+
+    #[sqlx::test]
+    pub async fn export(pool: PgPool) {
+        let tools = Category::insert_into_db(&pool, "Tools", "Things to fix with", None)
+            .await
+            .unwrap();
+        let garage = Location::insert_into_db(&pool, "Garage", "Where the car lives", None, None)
+            .await
+            .unwrap();
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Tools", Some(garage), None)
+            .await
+            .unwrap();
+        let kitchen = Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
+            .await
+            .unwrap();
+        for (name, location_id, category_id) in [
+            ("Car", garage, None),
+            ("Hammer", shelf, Some(tools)),
+            ("Pan", kitchen, None),
+        ] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    location_id: Some(location_id),
+                    category_id,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let export = Location::export(&pool, garage).await.unwrap();
+
+        assert_eq!(export.tree.location.id, garage);
+        assert_eq!(export.tree.children.len(), 1);
+        assert_eq!(export.tree.children[0].location.id, shelf);
+        let items = export
+            .items
+            .iter()
+            .map(|i| (i.item.name.as_str(), i.category.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(items, vec![("Car", None), ("Hammer", Some("Tools"))]);
+    }
+
+    #[sqlx::test]
+    pub async fn occupancy(pool: PgPool) {
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Books", None, Some(3))
+            .await
+            .unwrap();
+        let attic = Location::insert_into_db(&pool, "Attic", "Boxes", None, None)
+            .await
+            .unwrap();
+        for name in ["Dune", "Emma"] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    location_id: Some(shelf),
+                    ..NewItem::new(name, "Book", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let occupancy = Location::occupancy(&pool, shelf).await.unwrap();
+
+        assert_eq!(occupancy.capacity, Some(3));
+        assert_eq!(occupancy.used, 2);
+        assert_eq!(occupancy.remaining, Some(1));
+
+        let occupancy = Location::occupancy(&pool, attic).await.unwrap();
+
+        assert_eq!(occupancy.capacity, None);
+        assert_eq!(occupancy.used, 0);
+        assert_eq!(occupancy.remaining, None);
+
+        let mut transaction = pool.begin().await.unwrap();
+        let occupancy = Location::lock_occupancy(&mut transaction, shelf)
+            .await
+            .unwrap();
+        assert_eq!(occupancy.remaining, Some(1));
+        assert!(Location::lock_occupancy(&mut transaction, attic + 1)
+            .await
+            .is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn receive_items(pool: PgPool) {
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Books", None, None)
+            .await
+            .unwrap();
+        let dune = Item::insert_into_db(&pool, &NewItem::new("Dune", "Book", Utc::now()))
+            .await
+            .unwrap();
+
+        let drawer = Location::insert_into_db(&pool, "Drawer", "Tools", None, Some(1))
+            .await
+            .unwrap();
+        let emma = Item::insert_into_db(&pool, &NewItem::new("Emma", "Book", Utc::now()))
+            .await
+            .unwrap();
+
+        let moved = Location::receive_items(&pool, shelf, &[dune])
+            .await
+            .unwrap();
+        assert_eq!(moved, Received::Moved(vec![dune]));
+        let moved = Location::receive_items(&pool, shelf, &[dune])
+            .await
+            .unwrap();
+        assert_eq!(moved, Received::Moved(Vec::new()));
+
+        assert!(Location::receive_items(&pool, shelf, &[emma, emma + 1])
+            .await
+            .is_err());
+        assert_eq!(
+            Item::read_from_db_by_id(&pool, emma)
+                .await
+                .unwrap()
+                .location_id,
+            None
+        );
+        assert!(Location::receive_items(&pool, drawer + 1, &[dune])
+            .await
+            .is_err());
+
+        let moved = Location::receive_items(&pool, drawer, &[dune, emma])
+            .await
+            .unwrap();
+        assert_eq!(
+            moved,
+            Received::Full {
+                remaining: 1,
+                arriving: 2
+            }
+        );
+        let moved = Location::receive_items(&pool, drawer, &[dune])
+            .await
+            .unwrap();
+        assert_eq!(moved, Received::Moved(vec![dune]));
+        let moved = Location::receive_items(&pool, drawer, &[dune])
+            .await
+            .unwrap();
+        assert_eq!(moved, Received::Moved(Vec::new()));
+    }
+
+    #[sqlx::test]
+    pub async fn item_counts(pool: PgPool) {
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Books", None, None)
+            .await
+            .unwrap();
+        let drawer = Location::insert_into_db(&pool, "Drawer", "Tools", None, None)
+            .await
+            .unwrap();
+        let attic = Location::insert_into_db(&pool, "Attic", "Boxes", None, None)
+            .await
+            .unwrap();
+        for (name, location_id) in [("Dune", shelf), ("Hammer", drawer), ("Emma", shelf)] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    location_id: Some(location_id),
+                    ..NewItem::new(name, "Thing", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let counts = Location::item_counts(&pool).await.unwrap();
+
+        let counts = counts
+            .iter()
+            .map(|c| (c.location_id, c.item_count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![(shelf, 2), (drawer, 1), (attic, 0)]);
+    }
+
+    #[sqlx::test]
+    pub async fn recently_active(pool: PgPool) {
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Books", None, None)
+            .await
+            .unwrap();
+        let drawer = Location::insert_into_db(&pool, "Drawer", "Tools", None, None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Attic", "Boxes", None, None)
+            .await
+            .unwrap();
+        let mut item_ids = Vec::new();
+        for (name, location_id) in [("Dune", shelf), ("Hammer", drawer)] {
+            let id = Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    location_id: Some(location_id),
+                    ..NewItem::new(name, "Thing", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+            item_ids.push(id);
+        }
+
+        let order = |locations: Vec<RecentlyActiveLocation>| {
+            locations.iter().map(|l| l.location.id).collect::<Vec<_>>()
+        };
+        let locations = Location::recently_active(&pool, 10).await.unwrap();
+        assert_eq!(order(locations), vec![drawer, shelf]);
+
+        Location::receive_items(&pool, shelf, &item_ids[1..])
+            .await
+            .unwrap();
+        let locations = Location::recently_active(&pool, 10).await.unwrap();
+        assert_eq!(order(locations), vec![shelf]);
+
+        let locations = Location::recently_active(&pool, 1).await.unwrap();
+        assert_eq!(locations.len(), 1);
+    }
 }
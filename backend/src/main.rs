@@ -1,16 +1,34 @@
+mod audit;
+mod category;
+mod config;
 mod error;
+mod extractor;
+mod integrity;
 mod item;
 mod location;
+mod note;
 mod picture;
-mod category;
+mod retry;
 mod router;
+mod settings;
+mod shutdown;
+mod state;
+mod upsert;
+mod validation;
 
-use std::str::FromStr;
+use std::{
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::Result;
-use log::info;
+use config::{Config, RouteTimeout};
+use log::{info, warn};
+use picture::PictureInfo;
 use simple_logger::SimpleLogger;
 use sqlx::PgPool;
+use state::AppState;
 use structopt::StructOpt;
 
 #[derive(Debug, Clone, StructOpt)]
@@ -28,6 +46,72 @@ pub struct Opts {
 
     #[structopt(short, long, default_value = "info")]
     log_level: String,
+
+    /// Page size used by list endpoints when no `limit` is given, at least 1
+    #[structopt(long, default_value = "100")]
+    default_page_size: NonZeroU32,
+
+    #[structopt(long, use_delimiter = true)]
+    cors_origins: Vec<String>,
+
+    #[structopt(long, default_value = "3600")]
+    presign_expiry_secs: u32,
+
+    #[structopt(long, default_value = "30")]
+    shutdown_grace_secs: u64,
+
+    #[structopt(long, default_value = "0")]
+    cache_max_age_secs: u64,
+
+    #[structopt(long, default_value = "1000")]
+    slow_request_ms: u64,
+
+    /// Write detailed request logs for one in this many requests, at least 1
+    #[structopt(long, default_value = "1")]
+    log_sample_rate: NonZeroU64,
+
+    /// Object storage calls made at once, at least 1
+    #[structopt(long, default_value = "16")]
+    s3_max_concurrency: NonZeroUsize,
+
+    /// Headers to redact from logs in addition to the defaults
+    #[structopt(long, use_delimiter = true)]
+    redact_headers: Vec<String>,
+
+    /// Attempts a database read gets when the database cannot be reached, at least 1
+    #[structopt(long, default_value = "3")]
+    db_read_attempts: NonZeroU32,
+
+    /// Use camelCase keys in JSON bodies instead of snake_case
+    #[structopt(long)]
+    camel_case: bool,
+
+    /// Requests handled at once, 0 removes the limit
+    #[structopt(long, default_value = "1024")]
+    max_concurrent_requests: usize,
+
+    #[structopt(long, default_value = "100")]
+    queue_timeout_ms: u64,
+
+    /// Time a request may take before it is answered with 504, 0 disables the timeout. Multipart
+    /// uploads are never timed out
+    #[structopt(long, default_value = "30000")]
+    request_timeout_ms: u64,
+
+    /// Timeouts for paths below a prefix, as `prefix=ms`, e.g. `/api/items=5000`. The prefix
+    /// matches whole path segments
+    #[structopt(long, use_delimiter = true)]
+    route_timeouts: Vec<RouteTimeout>,
+
+    /// Largest JSON request body accepted by the write endpoints, in bytes
+    #[structopt(long, default_value = "1048576")]
+    max_json_body_bytes: usize,
+
+    #[structopt(long, default_value = "255")]
+    max_name_len: usize,
+
+    #[structopt(long, default_value = "4096")]
+    max_description_len: usize,
 }
 
 #[tokio::main]
@@ -40,8 +124,61 @@ async fn main() -> Result<()> {
     info!("Connecting to DB at {}", opts.db_url);
     let connection = PgPool::connect(&opts.db_url).await.unwrap();
 
-    let router = router::create_router(connection);
+    let storage_configured = match PictureInfo::check_storage_config() {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(
+                "File storage is not configured, picture uploads are disabled: {}",
+                e
+            );
+            false
+        }
+    };
+
+    let config = Config {
+        default_page_size: i64::from(opts.default_page_size.get()),
+        cors_origins: opts.cors_origins,
+        presign_expiry_secs: opts.presign_expiry_secs,
+        cache_max_age_secs: opts.cache_max_age_secs,
+        slow_request_ms: opts.slow_request_ms,
+        log_sample_rate: opts.log_sample_rate,
+        redacted_headers: config::DEFAULT_REDACTED_HEADERS
+            .iter()
+            .map(|header| header.to_string())
+            .chain(
+                opts.redact_headers
+                    .iter()
+                    .map(|header| header.to_lowercase()),
+            )
+            .collect(),
+        storage_configured,
+        camel_case: opts.camel_case,
+        max_concurrent_requests: opts.max_concurrent_requests,
+        queue_timeout_ms: opts.queue_timeout_ms,
+        request_timeout_ms: opts.request_timeout_ms,
+        route_timeouts: opts.route_timeouts,
+        max_json_body_bytes: opts.max_json_body_bytes,
+        max_name_len: opts.max_name_len,
+        max_description_len: opts.max_description_len,
+        s3_max_concurrency: opts.s3_max_concurrency,
+        db_read_attempts: opts.db_read_attempts.get(),
+    };
+
+    let state = AppState::new(connection, config);
+    if let Err(e) = state.reload_settings().await {
+        warn!("Ignoring stored settings: {}", e);
+    }
+    let in_flight = state.in_flight.clone();
+    let router = router::create_router(state);
     let listener = tokio::net::TcpListener::bind(opts.host).await?;
-    axum::serve(listener, router).await?;
+    shutdown::serve_with_drain(
+        listener,
+        router,
+        in_flight,
+        shutdown::shutdown_signal(),
+        Duration::from_secs(opts.shutdown_grace_secs),
+    )
+    .await?;
+    info!("Shut down");
     Ok(())
 }
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+/// Dated remark about an item
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Note {
+    pub id: i32,
+    pub item_id: i32,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewNote {
+    pub body: String,
+}
+
+impl Note {
+    pub async fn insert_into_db(
+        executor: impl PgExecutor<'_>,
+        item_id: i32,
+        body: &str,
+    ) -> Result<Note> {
+        let note = sqlx::query_as::<_, Note>(
+            "INSERT INTO item_notes (item_id, body) VALUES ($1, $2) RETURNING *",
+        )
+        .bind(item_id)
+        .bind(body)
+        .fetch_one(executor)
+        .await?;
+        Ok(note)
+    }
+
+    /// Reads the notes of an item, newest first
+    pub async fn read_for_item(pool: &PgPool, item_id: i32) -> Result<Vec<Note>> {
+        let notes = sqlx::query_as::<_, Note>(
+            "SELECT * FROM item_notes n WHERE n.item_id = $1 ORDER BY n.created_at DESC, n.id DESC",
+        )
+        .bind(item_id)
+        .fetch_all(pool)
+        .await?;
+        Ok(notes)
+    }
+
+    /// Deletes a note by id, returning whether there was one to delete
+    pub async fn delete_from_db(executor: impl PgExecutor<'_>, id: i32) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM item_notes n WHERE n.id = $1")
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::item::{Item, NewItem};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    pub async fn add_list_and_delete(pool: PgPool) {
+        let item_id =
+            Item::insert_into_db(&pool, &NewItem::new("Lader", "Til mobilen", Utc::now()))
+                .await
+                .unwrap();
+        let lent = Note::insert_into_db(&pool, item_id, "Lånt ut til Ola")
+            .await
+            .unwrap();
+        let broken = Note::insert_into_db(&pool, item_id, "Må repareres")
+            .await
+            .unwrap();
+
+        let notes = Note::read_for_item(&pool, item_id).await.unwrap();
+        let ids = notes.iter().map(|n| n.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![broken.id, lent.id]);
+
+        assert!(Note::delete_from_db(&pool, lent.id).await.unwrap());
+        assert!(!Note::delete_from_db(&pool, lent.id).await.unwrap());
+
+        let notes = Note::read_for_item(&pool, item_id).await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].body, "Må repareres".to_string());
+    }
+}
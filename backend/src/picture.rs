@@ -1,9 +1,57 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::Arc,
+};
+
 use anyhow::Result;
-use s3::{creds::Credentials, Bucket, BucketConfiguration, Region};
+use futures::stream::BoxStream;
+use log::warn;
+use s3::{creds::Credentials, error::S3Error, Bucket, BucketConfiguration, Region};
 use serde::{Deserialize, Serialize};
 use sha256::digest;
-use sqlx::{prelude::FromRow, PgPool};
+use sqlx::{prelude::FromRow, Acquire, PgConnection, PgPool, Postgres};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+
+/// Bucket holding every picture, named by the hash of its bytes. Pictures stored before it was
+/// introduced live in per-item buckets, which is why each row records its bucket
+pub const CONTENT_BUCKET: &str = "pictures";
+
+/// Prefix of the per-item buckets used before [`CONTENT_BUCKET`], followed by the item id
+const LEGACY_BUCKET_PREFIX: &str = "item-";
+
+/// Whether `name` is a bucket pictures are kept in, either [`CONTENT_BUCKET`] or a per-item one
+pub fn is_picture_bucket(name: &str) -> bool {
+    name == CONTENT_BUCKET
+        || name
+            .strip_prefix(LEGACY_BUCKET_PREFIX)
+            .is_some_and(|item_id| item_id.parse::<u32>().is_ok())
+}
 
+/// Most objects returned by one call to [`PictureInfo::list_objects`]
+pub const MAX_OBJECT_PAGE_SIZE: usize = 1000;
+
+/// Slots for object storage calls, shared by every clone
+#[derive(Debug, Clone)]
+pub struct S3Permits(Arc<Semaphore>);
+
+impl S3Permits {
+    /// Allows `permits` object storage calls at once
+    pub fn new(permits: NonZeroUsize) -> Self {
+        Self(Arc::new(Semaphore::new(permits.get())))
+    }
+
+    /// Waits until another object storage call may start
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit> {
+        let permit = self.0.clone().acquire_owned().await?;
+        Ok(permit)
+    }
+}
+
+#[cfg(test)]
 pub type Picture = Vec<u8>;
 
 #[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
@@ -13,42 +61,381 @@ pub struct PictureInfo {
     description: String,
     hash: String,
     object_storage_location: String,
+    /// Place of the picture among those of its item, counting from 1
+    position: i32,
+    /// Size of the stored object in bytes, unknown for pictures stored before it was recorded
+    size: Option<i64>,
+}
+
+/// First line of the CSV written by [`PictureInfo::csv_row`]
+pub const CSV_HEADER: &str = "id,item_id,description,hash,size\n";
+
+/// Quotes a CSV field when it holds a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Time-limited link to a stored picture
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PictureUrl {
+    pub picture_id: i32,
+    pub description: String,
+    pub url: String,
+}
+
+/// Bytes stored in a single bucket
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BucketUsage {
+    pub bucket: String,
+    pub bytes: u64,
+}
+
+/// Bytes stored across all buckets holding pictures
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StorageUsage {
+    pub buckets: Vec<BucketUsage>,
+    pub total: u64,
+}
+
+/// Object as listed by object storage
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+}
+
+/// One page of a bucket listing, `next_token` continues it when more objects remain
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ObjectPage {
+    pub objects: Vec<StoredObject>,
+    pub next_token: Option<String>,
+}
+
+/// Outcome of [`PictureInfo::move_to_item`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Moved {
+    /// The picture now belongs to the item. Holds the (bucket, hash) of an old object to pass to
+    /// [`PictureInfo::delete_unused_objects`] once the move is committed
+    Moved(Option<(String, String)>),
+    /// Nothing changed because the picture already belongs to the item
+    AlreadyThere,
+}
+
+/// Picture row whose object is not in its bucket
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MissingObject {
+    pub picture_id: i32,
+    pub bucket: String,
+    pub hash: String,
+}
+
+/// Object in a picture bucket that no picture row points at
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OrphanObject {
+    pub bucket: String,
+    pub key: String,
 }
 
 impl PictureInfo {
-    pub fn new(
-        id: i32,
+    pub async fn read_from_db(pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<PictureInfo>> {
+        let items = sqlx::query_as::<_, PictureInfo>(
+            "SELECT * FROM pictures ORDER BY id LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+        Ok(items)
+    }
+
+    /// Streams the pictures in id order, only those of `item_id` when given
+    pub fn stream_from_db(
+        pool: &PgPool,
+        item_id: Option<i32>,
+    ) -> BoxStream<'_, Result<PictureInfo, sqlx::Error>> {
+        sqlx::query_as::<_, PictureInfo>(
+            "SELECT * FROM pictures p WHERE $1::INTEGER IS NULL OR p.item_id = $1 ORDER BY p.id",
+        )
+        .bind(item_id)
+        .fetch(pool)
+    }
+
+    /// Formats the picture as a line of CSV matching [`CSV_HEADER`], leaving an unknown size empty
+    pub fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{}\n",
+            self.id,
+            self.item_id,
+            csv_field(&self.description),
+            csv_field(&self.hash),
+            self.size.map(|size| size.to_string()).unwrap_or_default()
+        )
+    }
+
+    pub async fn count(pool: &PgPool) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM pictures")
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
+    /// Creates a presigned link to a stored picture, valid for `expiry_secs`
+    pub async fn presigned_url(bucket_name: &str, hash: &str, expiry_secs: u32) -> Result<String> {
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let bucket = Bucket::new(bucket_name, region, credentials)?.with_path_style();
+        let url = bucket.presign_get(hash, expiry_secs, None).await?;
+        Ok(url)
+    }
+
+    /// Creates presigned links to the pictures of an item in position order, valid for `expiry_secs`
+    pub async fn presigned_urls_for_item(
+        pool: &PgPool,
         item_id: i32,
-        description: String,
-        hash: String,
-        object_storage_location: String,
-    ) -> Self {
-        Self {
-            id,
-            item_id,
-            description,
-            hash,
-            object_storage_location,
+        expiry_secs: u32,
+    ) -> Result<Vec<PictureUrl>> {
+        let picture_infos = sqlx::query_as::<_, PictureInfo>(
+            "SELECT * FROM pictures p WHERE p.item_id = $1 ORDER BY p.position, p.id",
+        )
+        .bind(item_id)
+        .fetch_all(pool)
+        .await?;
+        if picture_infos.is_empty() {
+            return Ok(Vec::new());
         }
+
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let mut result = Vec::new();
+        for picture_info in picture_infos {
+            let bucket = Bucket::new(
+                &picture_info.object_storage_location,
+                region.clone(),
+                credentials.clone(),
+            )?
+            .with_path_style();
+            let url = bucket
+                .presign_get(&picture_info.hash, expiry_secs, None)
+                .await?;
+            result.push(PictureUrl {
+                picture_id: picture_info.id,
+                description: picture_info.description,
+                url,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Sums the sizes of the objects in every bucket that holds pictures, listing buckets concurrently
+    pub async fn storage_usage(pool: &PgPool, permits: &S3Permits) -> Result<StorageUsage> {
+        let bucket_names = sqlx::query_scalar::<_, String>(
+            "SELECT DISTINCT p.object_storage_location FROM pictures p ORDER BY p.object_storage_location",
+        )
+        .fetch_all(pool)
+        .await?;
+        if bucket_names.is_empty() {
+            return Ok(StorageUsage {
+                buckets: Vec::new(),
+                total: 0,
+            });
+        }
+
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let mut listings = JoinSet::new();
+        for bucket_name in bucket_names {
+            let bucket =
+                Bucket::new(&bucket_name, region.clone(), credentials.clone())?.with_path_style();
+            let permits = permits.clone();
+            listings.spawn(async move {
+                let _permit = permits.acquire().await?;
+                let pages = bucket.list(String::new(), None).await?;
+                let bytes = pages
+                    .iter()
+                    .flat_map(|page| page.contents.iter())
+                    .map(|object| object.size)
+                    .sum();
+                anyhow::Ok(BucketUsage {
+                    bucket: bucket_name,
+                    bytes,
+                })
+            });
+        }
+
+        let mut buckets = Vec::new();
+        while let Some(listing) = listings.join_next().await {
+            buckets.push(listing??);
+        }
+        buckets.sort_by(|a, b| a.bucket.cmp(&b.bucket));
+        let total = buckets.iter().map(|usage| usage.bytes).sum();
+        Ok(StorageUsage { buckets, total })
     }
 
-    pub async fn read_from_db(pool: &PgPool) -> Result<Vec<PictureInfo>> {
-        let items = sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures")
+    /// Lists up to `limit` objects of a bucket, continuing after `token`. Returns `None` when the
+    /// bucket does not exist
+    pub async fn list_objects(
+        permits: &S3Permits,
+        bucket_name: &str,
+        token: Option<String>,
+        limit: usize,
+    ) -> Result<Option<ObjectPage>> {
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let bucket = Bucket::new(bucket_name, region, credentials)?.with_path_style();
+        let _permit = permits.acquire().await?;
+        if !bucket.exists().await? {
+            return Ok(None);
+        }
+        let (page, _) = bucket
+            .list_page(
+                String::new(),
+                None,
+                token,
+                None,
+                Some(limit.clamp(1, MAX_OBJECT_PAGE_SIZE)),
+            )
+            .await?;
+        let objects = page
+            .contents
+            .into_iter()
+            .map(|object| StoredObject {
+                key: object.key,
+                size: object.size,
+            })
+            .collect();
+        let next_token = if page.is_truncated {
+            page.next_continuation_token
+        } else {
+            None
+        };
+        Ok(Some(ObjectPage {
+            objects,
+            next_token,
+        }))
+    }
+
+    /// Compares the picture rows with the objects in their buckets, returning rows whose object
+    /// is gone and objects no row points at
+    pub async fn storage_mismatches(
+        pool: &PgPool,
+        permits: &S3Permits,
+    ) -> Result<(Vec<MissingObject>, Vec<OrphanObject>)> {
+        let picture_infos = sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures ORDER BY id")
             .fetch_all(pool)
             .await?;
-        Ok(items)
+        let mut bucket_names: Vec<&str> = picture_infos
+            .iter()
+            .map(|picture_info| picture_info.object_storage_location.as_str())
+            .chain([CONTENT_BUCKET])
+            .collect();
+        bucket_names.sort_unstable();
+        bucket_names.dedup();
+
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let mut keys_by_bucket = HashMap::new();
+        for bucket_name in bucket_names {
+            let bucket =
+                Bucket::new(bucket_name, region.clone(), credentials.clone())?.with_path_style();
+            let _permit = permits.acquire().await?;
+            let keys: HashSet<String> = if bucket.exists().await? {
+                bucket
+                    .list(String::new(), None)
+                    .await?
+                    .into_iter()
+                    .flat_map(|page| page.contents)
+                    .map(|object| object.key)
+                    .collect()
+            } else {
+                HashSet::new()
+            };
+            keys_by_bucket.insert(bucket_name.to_string(), keys);
+        }
+
+        let missing = picture_infos
+            .iter()
+            .filter(|picture_info| {
+                !keys_by_bucket[&picture_info.object_storage_location].contains(&picture_info.hash)
+            })
+            .map(|picture_info| MissingObject {
+                picture_id: picture_info.id,
+                bucket: picture_info.object_storage_location.clone(),
+                hash: picture_info.hash.clone(),
+            })
+            .collect();
+
+        let referenced: HashSet<(&str, &str)> = picture_infos
+            .iter()
+            .map(|picture_info| {
+                (
+                    picture_info.object_storage_location.as_str(),
+                    picture_info.hash.as_str(),
+                )
+            })
+            .collect();
+        let mut orphans: Vec<OrphanObject> = keys_by_bucket
+            .iter()
+            .flat_map(|(bucket, keys)| keys.iter().map(move |key| (bucket, key)))
+            .filter(|(bucket, key)| !referenced.contains(&(bucket.as_str(), key.as_str())))
+            .map(|(bucket, key)| OrphanObject {
+                bucket: bucket.clone(),
+                key: key.clone(),
+            })
+            .collect();
+        orphans.sort_by(|a, b| (&a.bucket, &a.key).cmp(&(&b.bucket, &b.key)));
+
+        Ok((missing, orphans))
     }
 
-    pub async fn read_from_db_and_s3(pool: &PgPool) -> Result<Vec<(PictureInfo, Picture)>> {
+    /// Checks every picture's object and returns the ids of pictures whose object is gone.
+    /// The checks run concurrently, as many at a time as the storage permits allow
+    pub async fn broken_pictures(pool: &PgPool, permits: &S3Permits) -> Result<Vec<i32>> {
+        let picture_infos = sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures ORDER BY id")
+            .fetch_all(pool)
+            .await?;
         let (credentials, region) = Self::get_s3_credentials()?;
-        let picture_infos = sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures")
+
+        let mut checks = JoinSet::new();
+        for picture_info in picture_infos {
+            let bucket = Bucket::new(
+                &picture_info.object_storage_location,
+                region.clone(),
+                credentials.clone(),
+            )?
+            .with_path_style();
+            let permits = permits.clone();
+            checks.spawn(async move {
+                let _permit = permits.acquire().await?;
+                match bucket.head_object(&picture_info.hash).await {
+                    Ok(_) => anyhow::Ok(None),
+                    Err(S3Error::HttpFailWithBody(404, _)) => Ok(Some(picture_info.id)),
+                    Err(e) => Err(e.into()),
+                }
+            });
+        }
+
+        let mut broken = Vec::new();
+        while let Some(check) = checks.join_next().await {
+            broken.extend(check??);
+        }
+        broken.sort_unstable();
+        Ok(broken)
+    }
+
+    #[cfg(test)]
+    pub async fn read_from_db_and_s3(
+        pool: &PgPool,
+        permits: &S3Permits,
+    ) -> Result<Vec<(PictureInfo, Picture)>> {
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let picture_infos = sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures ORDER BY id")
             .fetch_all(pool)
             .await?;
 
         let mut result: Vec<(PictureInfo, Picture)> = Vec::new();
         for picture_info in picture_infos {
             let picture = Self::get_from_s3(
-                picture_info.item_id,
+                permits,
+                &picture_info.object_storage_location,
                 &picture_info.hash,
                 credentials.clone(),
                 region.clone(),
@@ -59,87 +446,283 @@ impl PictureInfo {
         Ok(result)
     }
 
-    fn into_bucket_name(item_id: i32) -> String {
-        format!("item-{}", item_id)
+    /// Checks that object storage credentials and region can be loaded from the environment
+    pub fn check_storage_config() -> Result<()> {
+        Self::get_s3_credentials()?;
+        Ok(())
     }
 
     fn get_s3_credentials() -> Result<(Credentials, Region)> {
         Ok((Credentials::default()?, Region::from_default_env()?))
     }
 
+    /// Checks that object storage answers by listing its buckets
+    pub async fn ping_s3(permits: &S3Permits) -> Result<()> {
+        let (credentials, region) = Self::get_s3_credentials()?;
+        let _permit = permits.acquire().await?;
+        Bucket::list_buckets(region, credentials).await?;
+        Ok(())
+    }
+
+    /// Stores a picture of an item. Objects are named by the hash of their bytes in a shared
+    /// bucket, so identical pictures are uploaded once however many items they belong to
     pub async fn insert_into_db(
-        pool: &PgPool,
+        connection: impl Acquire<'_, Database = Postgres>,
+        permits: &S3Permits,
         item_id: i32,
         description: &str,
         picture: &[u8],
-    ) -> Result<()> {
+    ) -> Result<i32> {
         let hash = digest(picture);
         let (credentials, region) = Self::get_s3_credentials()?;
-        Self::put_into_s3(item_id, &hash, picture, credentials, region).await?;
-        sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location) VALUES ($1, $2, $3, $4)").bind(item_id).bind(description).bind(hash.clone()).bind(Self::into_bucket_name(item_id)).execute(pool).await?;
+
+        // Waiting for a storage slot before opening the transaction keeps queued uploads from
+        // holding database connections
+        let _permit = permits.acquire().await?;
+        // Holding the hash lock until the row is committed keeps cleanup from deleting the object
+        // between the upload and the insert
+        let mut transaction = connection.begin().await?;
+        Self::lock_hash(&mut transaction, &hash).await?;
+        Self::store_object(&hash, picture, credentials, region).await?;
+
+        // Locking the item serializes concurrent uploads for it, so no two get the same position
+        sqlx::query("SELECT i.id FROM items i WHERE i.id = $1 FOR UPDATE")
+            .bind(item_id)
+            .fetch_one(&mut *transaction)
+            .await?;
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO pictures (item_id, description, hash, object_storage_location, position, size) SELECT $1, $2, $3, $4, COALESCE(MAX(p.position), 0) + 1, $5 FROM pictures p WHERE p.item_id = $1 RETURNING id",
+        )
+        .bind(item_id)
+        .bind(description)
+        .bind(&hash)
+        .bind(CONTENT_BUCKET)
+        .bind(picture.len() as i64)
+        .fetch_one(&mut *transaction)
+        .await?;
+        sqlx::query("UPDATE items SET picture_count = picture_count + 1 WHERE id = $1")
+            .bind(item_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(id)
+    }
+
+    /// Attaches a picture to another item, placing it after that item's pictures. A picture still
+    /// kept in a per-item bucket is first copied to the content bucket. The picture stays locked
+    /// while it moves, so concurrent moves of it take turns
+    pub async fn move_to_item(
+        connection: impl Acquire<'_, Database = Postgres>,
+        permits: &S3Permits,
+        id: i32,
+        to_item_id: i32,
+    ) -> Result<Moved> {
+        let mut transaction = connection.begin().await?;
+        let picture_info =
+            sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures p WHERE p.id = $1 FOR UPDATE")
+                .bind(id)
+                .fetch_one(&mut *transaction)
+                .await?;
+        if picture_info.item_id == to_item_id {
+            return Ok(Moved::AlreadyThere);
+        }
+        let legacy = picture_info.object_storage_location != CONTENT_BUCKET;
+        Self::lock_hash(&mut transaction, &picture_info.hash).await?;
+        if legacy {
+            let (credentials, region) = Self::get_s3_credentials()?;
+            let picture = Self::get_from_s3(
+                permits,
+                &picture_info.object_storage_location,
+                &picture_info.hash,
+                credentials.clone(),
+                region.clone(),
+            )
+            .await?;
+            Self::put_into_s3(permits, &picture_info.hash, &picture, credentials, region).await?;
+        }
+
+        sqlx::query("SELECT i.id FROM items i WHERE i.id = $1 FOR UPDATE")
+            .bind(to_item_id)
+            .fetch_one(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "UPDATE pictures SET item_id = $1, object_storage_location = $2, position = (SELECT COALESCE(MAX(p.position), 0) + 1 FROM pictures p WHERE p.item_id = $1) WHERE id = $3",
+        )
+        .bind(to_item_id)
+        .bind(CONTENT_BUCKET)
+        .bind(id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query("UPDATE items SET picture_count = picture_count + CASE WHEN id = $1 THEN 1 ELSE -1 END WHERE id IN ($1, $2)")
+            .bind(to_item_id)
+            .bind(picture_info.item_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+
+        Ok(Moved::Moved(legacy.then_some((
+            picture_info.object_storage_location,
+            picture_info.hash,
+        ))))
+    }
+
+    /// Takes a lock on a content hash until the transaction ends, so uploads and object cleanup
+    /// for the same bytes never interleave
+    async fn lock_hash(connection: &mut PgConnection, hash: &str) -> Result<()> {
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(hash)
+            .execute(connection)
+            .await?;
         Ok(())
     }
 
-    pub async fn put_into_s3(
+    /// Deletes an object unless a picture row still points at it. The hash lock is held while
+    /// checking and deleting, so an upload of the same bytes waits until the object is gone
+    async fn delete_if_unused(
+        pool: &PgPool,
+        permits: &S3Permits,
+        bucket_name: &str,
+        hash: &str,
+    ) -> Result<()> {
+        let mut transaction = pool.begin().await?;
+        Self::lock_hash(&mut transaction, hash).await?;
+        let still_used = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM pictures p WHERE p.object_storage_location = $1 AND p.hash = $2)",
+        )
+        .bind(bucket_name)
+        .bind(hash)
+        .fetch_one(&mut *transaction)
+        .await?;
+        if !still_used {
+            let (credentials, region) = Self::get_s3_credentials()?;
+            Self::delete_from_s3(permits, bucket_name, hash, credentials, region).await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Removes every picture of an item, returning how many were removed and the (bucket, hash)
+    /// of their objects. The objects are to be passed to [`PictureInfo::delete_unused_objects`]
+    /// once the removal is committed
+    pub async fn delete_for_item(
+        connection: impl Acquire<'_, Database = Postgres>,
         item_id: i32,
+    ) -> Result<(u64, Vec<(String, String)>)> {
+        let mut transaction = connection.begin().await?;
+        sqlx::query("SELECT i.id FROM items i WHERE i.id = $1 FOR UPDATE")
+            .bind(item_id)
+            .fetch_one(&mut *transaction)
+            .await?;
+        let removed = sqlx::query_as::<_, (String, String)>(
+            "DELETE FROM pictures WHERE item_id = $1 RETURNING object_storage_location, hash",
+        )
+        .bind(item_id)
+        .fetch_all(&mut *transaction)
+        .await?;
+        sqlx::query("UPDATE items SET picture_count = 0 WHERE id = $1")
+            .bind(item_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+
+        let count = removed.len() as u64;
+        let objects = removed
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        Ok((count, objects))
+    }
+
+    /// Deletes the given (bucket, hash) objects that no picture row points at any more. An object
+    /// that cannot be deleted is only logged
+    pub async fn delete_unused_objects(
+        pool: &PgPool,
+        permits: &S3Permits,
+        objects: impl IntoIterator<Item = (String, String)>,
+    ) {
+        for (bucket_name, hash) in objects {
+            if let Err(e) = Self::delete_if_unused(pool, permits, &bucket_name, &hash).await {
+                warn!(
+                    "Could not delete object {} from {}: {}",
+                    hash, bucket_name, e
+                );
+            }
+        }
+    }
+
+    /// Uploads a picture to the content bucket under `hash` unless an object with that name is
+    /// already there, returning whether it was uploaded
+    pub async fn put_into_s3(
+        permits: &S3Permits,
         hash: &str,
         picture: &[u8],
         credentials: Credentials,
         region: Region,
-    ) -> Result<()> {
-        let bucket = Bucket::new(
-            &Self::into_bucket_name(item_id),
-            region.clone(),
-            credentials.clone(),
-        )?
-        .with_path_style();
+    ) -> Result<bool> {
+        let _permit = permits.acquire().await?;
+        Self::store_object(hash, picture, credentials, region).await
+    }
+
+    /// Does the work of [`PictureInfo::put_into_s3`] for a caller already holding a permit
+    async fn store_object(
+        hash: &str,
+        picture: &[u8],
+        credentials: Credentials,
+        region: Region,
+    ) -> Result<bool> {
+        let bucket =
+            Bucket::new(CONTENT_BUCKET, region.clone(), credentials.clone())?.with_path_style();
 
         if !bucket.exists().await? {
-            Bucket::create_with_path_style(
-                &Self::into_bucket_name(item_id),
+            let created = Bucket::create_with_path_style(
+                CONTENT_BUCKET,
                 region.clone(),
                 credentials.clone(),
                 BucketConfiguration::default(),
             )
-            .await?;
+            .await;
+            // Another upload may have created the bucket in the meantime
+            if let Err(e) = created {
+                if !bucket.exists().await? {
+                    return Err(e.into());
+                }
+            }
         }
 
+        match bucket.head_object(hash).await {
+            Ok(_) => return Ok(false),
+            Err(S3Error::HttpFailWithBody(404, _)) => {}
+            Err(e) => return Err(e.into()),
+        }
         bucket.put_object(hash, picture).await?;
 
-        Ok(())
+        Ok(true)
     }
 
     pub async fn get_from_s3(
-        item_id: i32,
+        permits: &S3Permits,
+        bucket_name: &str,
         hash: &str,
         credentials: Credentials,
         region: Region,
     ) -> Result<Vec<u8>> {
-        let bucket = Bucket::new(
-            &Self::into_bucket_name(item_id),
-            region.clone(),
-            credentials.clone(),
-        )
-        .unwrap()
-        .with_path_style();
+        let _permit = permits.acquire().await?;
+        let bucket = Bucket::new(bucket_name, region, credentials)?.with_path_style();
 
         let result = bucket.get_object(hash).await?;
         Ok(result.into())
     }
 
     pub async fn delete_from_s3(
-        item_id: i32,
+        permits: &S3Permits,
+        bucket_name: &str,
         hash: &str,
         credentials: Credentials,
         region: Region,
     ) -> Result<()> {
-        let bucket = Bucket::new(
-            &Self::into_bucket_name(item_id),
-            region.clone(),
-            credentials.clone(),
-        )
-        .unwrap()
-        .with_path_style();
+        let _permit = permits.acquire().await?;
+        let bucket = Bucket::new(bucket_name, region, credentials)?.with_path_style();
 
         bucket.delete_object(hash).await?;
 
@@ -149,29 +732,43 @@ impl PictureInfo {
 
 #[cfg(test)]
 mod tests {
-    use crate::item::Item;
+    use crate::{
+        config::Config,
+        item::{Item, NewItem},
+    };
 
     use super::*;
     use chrono::Utc;
     use sqlx::PgPool;
+    use std::time::Duration;
+
+    fn permits() -> S3Permits {
+        S3Permits::new(Config::default().s3_max_concurrency)
+    }
 
     #[sqlx::test]
     pub async fn create_and_read_from_everything(pool: PgPool) {
         let now = Utc::now();
-        Item::insert_into_db(&pool, "Stol", "Noe å sitte på", now)
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", now))
             .await
             .unwrap();
 
-        let items = Item::read_from_db(&pool).await;
+        let items = Item::read_from_db(&pool, 100, 0).await;
 
         assert!(items.is_ok());
         let items = items.unwrap();
         let item = items.first().unwrap();
-        PictureInfo::insert_into_db(&pool, item.id, "Bilde av stol", &[1, 2, 3, 4, 5])
-            .await
-            .unwrap();
+        PictureInfo::insert_into_db(
+            &pool,
+            &permits(),
+            item.id,
+            "Bilde av stol",
+            b"create_and_read_from_everything",
+        )
+        .await
+        .unwrap();
 
-        let pictures = PictureInfo::read_from_db(&pool).await;
+        let pictures = PictureInfo::read_from_db(&pool, 100, 0).await;
 
         dbg!(&pictures);
 
@@ -182,19 +779,27 @@ mod tests {
         assert_eq!(picture.id, 1);
         assert_eq!(picture.description, "Bilde av stol");
 
-        let pictures = PictureInfo::read_from_db_and_s3(&pool).await.unwrap();
+        let pictures = PictureInfo::read_from_db_and_s3(&pool, &permits())
+            .await
+            .unwrap();
 
         let (picture, content) = pictures.first().unwrap();
 
         assert_eq!(picture.id, 1);
         assert_eq!(picture.description, "Bilde av stol");
-        assert_eq!(content, &[1, 2, 3, 4, 5]);
+        assert_eq!(content, b"create_and_read_from_everything");
 
         let (credentials, region) = PictureInfo::get_s3_credentials().unwrap();
 
-        PictureInfo::delete_from_s3(picture.id, &picture.hash, credentials, region)
-            .await
-            .unwrap();
+        PictureInfo::delete_from_s3(
+            &permits(),
+            &picture.object_storage_location,
+            &picture.hash,
+            credentials,
+            region,
+        )
+        .await
+        .unwrap();
     }
 
     #[tokio::test]
@@ -206,12 +811,19 @@ mod tests {
             endpoint: "http://localhost:9000".to_owned(),
         };
 
-        let res =
-            PictureInfo::put_into_s3(123, "hei", &[1, 2, 3], credentials.clone(), region.clone())
-                .await;
+        let res = PictureInfo::put_into_s3(
+            &permits(),
+            "hei",
+            &[1, 2, 3],
+            credentials.clone(),
+            region.clone(),
+        )
+        .await;
         assert!(res.is_ok());
 
-        let res = PictureInfo::delete_from_s3(123, "hei", credentials, region).await;
+        let res =
+            PictureInfo::delete_from_s3(&permits(), CONTENT_BUCKET, "hei", credentials, region)
+                .await;
         assert!(res.is_ok());
     }
 
@@ -224,18 +836,390 @@ mod tests {
             endpoint: "http://localhost:9000".to_owned(),
         };
 
+        let res = PictureInfo::put_into_s3(
+            &permits(),
+            "hallo",
+            &[1, 2, 3],
+            credentials.clone(),
+            region.clone(),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let picture = PictureInfo::get_from_s3(
+            &permits(),
+            CONTENT_BUCKET,
+            "hallo",
+            credentials.clone(),
+            region.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(picture, &[1, 2, 3]);
+
         let res =
-            PictureInfo::put_into_s3(1234, "hei", &[1, 2, 3], credentials.clone(), region.clone())
+            PictureInfo::delete_from_s3(&permits(), CONTENT_BUCKET, "hallo", credentials, region)
                 .await;
         assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    pub async fn permits_limit_concurrent_calls() {
+        let permits = S3Permits::new(NonZeroUsize::new(2).unwrap());
+
+        let first = permits.acquire().await.unwrap();
+        let _second = permits.clone().acquire().await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), permits.acquire())
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), permits.acquire())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn concurrent_puts_share_permits() {
+        let permits = S3Permits::new(NonZeroUsize::new(2).unwrap());
+        let credentials =
+            Credentials::new(Some("admin"), Some("adminadmin"), None, None, None).unwrap();
+        let region = Region::Custom {
+            region: "no".to_owned(),
+            endpoint: "http://localhost:9000".to_owned(),
+        };
+
+        let uploads = (0..10).map(|i| {
+            let permits = permits.clone();
+            let credentials = credentials.clone();
+            let region = region.clone();
+            tokio::spawn(async move {
+                let hash = format!("concurrent_puts_share_permits-{}", i);
+                PictureInfo::put_into_s3(
+                    &permits,
+                    &hash,
+                    &[1, 2, 3],
+                    credentials.clone(),
+                    region.clone(),
+                )
+                .await?;
+                PictureInfo::delete_from_s3(&permits, CONTENT_BUCKET, &hash, credentials, region)
+                    .await
+            })
+        });
+
+        for upload in uploads.collect::<Vec<_>>() {
+            assert!(upload.await.unwrap().is_ok());
+        }
+        assert_eq!(permits.0.available_permits(), 2);
+    }
+
+    #[sqlx::test]
+    pub async fn storage_usage_covers_uploads(pool: PgPool) {
+        let item_id =
+            Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+                .await
+                .unwrap();
+        PictureInfo::insert_into_db(
+            &pool,
+            &permits(),
+            item_id,
+            "Forfra",
+            b"storage_usage_covers_uploads forfra",
+        )
+        .await
+        .unwrap();
+        PictureInfo::insert_into_db(
+            &pool,
+            &permits(),
+            item_id,
+            "Bakfra",
+            b"storage_usage_covers_uploads bakfra",
+        )
+        .await
+        .unwrap();
+
+        let usage = PictureInfo::storage_usage(&pool, &permits()).await.unwrap();
 
-        let picture = PictureInfo::get_from_s3(1234, "hei", credentials.clone(), region.clone())
+        let uploaded = ("storage_usage_covers_uploads forfra".len()
+            + "storage_usage_covers_uploads bakfra".len()) as u64;
+        assert!(usage.total >= uploaded);
+        assert!(usage
+            .buckets
+            .iter()
+            .any(|usage| usage.bucket == CONTENT_BUCKET && usage.bytes >= uploaded));
+
+        let (credentials, region) = PictureInfo::get_s3_credentials().unwrap();
+        for picture in PictureInfo::read_from_db(&pool, 100, 0).await.unwrap() {
+            PictureInfo::delete_from_s3(
+                &permits(),
+                CONTENT_BUCKET,
+                &picture.hash,
+                credentials.clone(),
+                region.clone(),
+            )
             .await
             .unwrap();
+        }
+    }
 
-        assert_eq!(picture, &[1, 2, 3]);
+    #[sqlx::test]
+    pub async fn identical_pictures_share_an_object(pool: PgPool) {
+        let mut hashes = Vec::new();
+        for name in ["Stol", "Krakk"] {
+            let item_id =
+                Item::insert_into_db(&pool, &NewItem::new(name, "Noe å sitte på", Utc::now()))
+                    .await
+                    .unwrap();
+            PictureInfo::insert_into_db(&pool, &permits(), item_id, "Samme bilde", &[9, 8, 7, 6])
+                .await
+                .unwrap();
+        }
+        for picture in PictureInfo::read_from_db(&pool, 100, 0).await.unwrap() {
+            assert_eq!(picture.object_storage_location, CONTENT_BUCKET);
+            hashes.push(picture.hash);
+        }
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes[0], hashes[1]);
 
-        let res = PictureInfo::delete_from_s3(1234, "hei", credentials, region).await;
-        assert!(res.is_ok());
+        let (credentials, region) = PictureInfo::get_s3_credentials().unwrap();
+        let bucket = Bucket::new(CONTENT_BUCKET, region.clone(), credentials.clone())
+            .unwrap()
+            .with_path_style();
+        let objects = bucket.list(hashes[0].clone(), None).await.unwrap();
+        assert_eq!(
+            objects
+                .iter()
+                .map(|page| page.contents.len())
+                .sum::<usize>(),
+            1
+        );
+
+        PictureInfo::delete_from_s3(&permits(), CONTENT_BUCKET, &hashes[0], credentials, region)
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    pub async fn concurrent_inserts_get_distinct_positions(pool: PgPool) {
+        let item_id =
+            Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+                .await
+                .unwrap();
+
+        let mut uploads = JoinSet::new();
+        for i in 0..6u8 {
+            let pool = pool.clone();
+            uploads.spawn(async move {
+                PictureInfo::insert_into_db(&pool, &permits(), item_id, "Stol", &[i, 1, 2]).await
+            });
+        }
+        while let Some(upload) = uploads.join_next().await {
+            upload.unwrap().unwrap();
+        }
+
+        let mut positions = PictureInfo::read_from_db(&pool, 100, 0)
+            .await
+            .unwrap()
+            .iter()
+            .map(|picture| picture.position)
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        assert_eq!(positions, (1..=6).collect::<Vec<_>>());
+    }
+
+    #[sqlx::test]
+    pub async fn move_to_item(pool: PgPool) {
+        let mut item_ids = Vec::new();
+        for name in ["Stol", "Bord"] {
+            let id = Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+            item_ids.push(id);
+        }
+        for (item_id, hash) in [(item_ids[0], "a"), (item_ids[1], "b")] {
+            sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location, position) VALUES ($1, 'Bilde', $2, $3, 1)")
+                .bind(item_id)
+                .bind(hash)
+                .bind(CONTENT_BUCKET)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let moved = PictureInfo::move_to_item(&pool, &permits(), 1, item_ids[1])
+            .await
+            .unwrap();
+        assert_eq!(moved, Moved::Moved(None));
+
+        let pictures = PictureInfo::read_from_db(&pool, 100, 0).await.unwrap();
+        let placement = pictures
+            .iter()
+            .map(|p| (p.hash.as_str(), p.item_id, p.position))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placement,
+            vec![("a", item_ids[1], 2), ("b", item_ids[1], 1)]
+        );
+
+        let picture_count = || {
+            sqlx::query_scalar::<_, i64>("SELECT i.picture_count FROM items i WHERE i.id = $1")
+                .bind(item_ids[1])
+                .fetch_one(&pool)
+        };
+        let before = picture_count().await.unwrap();
+        let moved = PictureInfo::move_to_item(&pool, &permits(), 1, item_ids[1])
+            .await
+            .unwrap();
+        assert_eq!(moved, Moved::AlreadyThere);
+        assert_eq!(picture_count().await.unwrap(), before);
+
+        assert!(PictureInfo::move_to_item(&pool, &permits(), 1, 999)
+            .await
+            .is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_for_item(pool: PgPool) {
+        let mut item_ids = Vec::new();
+        for name in ["Stol", "Bord"] {
+            let id = Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+            item_ids.push(id);
+        }
+        let mut hashes = Vec::new();
+        for (item_id, picture) in [
+            (item_ids[0], [1, 2]),
+            (item_ids[0], [3, 4]),
+            (item_ids[1], [5, 6]),
+        ] {
+            let id = PictureInfo::insert_into_db(&pool, &permits(), item_id, "Bilde", &picture)
+                .await
+                .unwrap();
+            let picture_info =
+                sqlx::query_as::<_, PictureInfo>("SELECT * FROM pictures p WHERE p.id = $1")
+                    .bind(id)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            hashes.push(picture_info.hash);
+        }
+
+        let (removed, objects) = PictureInfo::delete_for_item(&pool, item_ids[0])
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(objects.len(), 2);
+        PictureInfo::delete_unused_objects(&pool, &permits(), objects).await;
+
+        let pictures = PictureInfo::read_from_db(&pool, 100, 0).await.unwrap();
+        assert_eq!(
+            pictures.iter().map(|p| p.item_id).collect::<Vec<_>>(),
+            vec![item_ids[1]]
+        );
+        let (credentials, region) = PictureInfo::get_s3_credentials().unwrap();
+        let bucket = Bucket::new(CONTENT_BUCKET, region.clone(), credentials.clone())
+            .unwrap()
+            .with_path_style();
+        for hash in &hashes[..2] {
+            assert!(matches!(
+                bucket.head_object(hash).await,
+                Err(S3Error::HttpFailWithBody(404, _))
+            ));
+        }
+        assert!(bucket.head_object(&hashes[2]).await.is_ok());
+
+        PictureInfo::delete_from_s3(&permits(), CONTENT_BUCKET, &hashes[2], credentials, region)
+            .await
+            .unwrap();
+        assert!(PictureInfo::delete_for_item(&pool, 999).await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn broken_pictures(pool: PgPool) {
+        let item_id = Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for picture in [[4, 3, 2, 1], [4, 3, 2, 0]] {
+            let id = PictureInfo::insert_into_db(&pool, &permits(), item_id, "Bilde", &picture)
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+        assert!(PictureInfo::broken_pictures(&pool, &permits())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let (credentials, region) = PictureInfo::get_s3_credentials().unwrap();
+        PictureInfo::delete_from_s3(
+            &permits(),
+            CONTENT_BUCKET,
+            &digest([4u8, 3, 2, 0].as_slice()),
+            credentials.clone(),
+            region.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            PictureInfo::broken_pictures(&pool, &permits())
+                .await
+                .unwrap(),
+            vec![ids[1]]
+        );
+
+        PictureInfo::delete_from_s3(
+            &permits(),
+            CONTENT_BUCKET,
+            &digest([4u8, 3, 2, 1].as_slice()),
+            credentials,
+            region,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test]
+    pub async fn cleanup_spares_concurrent_upload(pool: PgPool) {
+        let permits = permits();
+        let picture = b"cleanup_spares_concurrent_upload";
+        for round in 0..5 {
+            let old = Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+                .await
+                .unwrap();
+            let new = Item::insert_into_db(&pool, &NewItem::new("Krakk", "Test", Utc::now()))
+                .await
+                .unwrap();
+            PictureInfo::insert_into_db(&pool, &permits, old, "Bilde", picture)
+                .await
+                .unwrap();
+
+            let (removed, inserted) = tokio::join!(
+                async {
+                    let (removed, objects) = PictureInfo::delete_for_item(&pool, old).await?;
+                    PictureInfo::delete_unused_objects(&pool, &permits, objects).await;
+                    anyhow::Ok(removed)
+                },
+                PictureInfo::insert_into_db(&pool, &permits, new, "Bilde", picture)
+            );
+            assert_eq!(removed.unwrap(), 1, "round {}", round);
+            inserted.unwrap();
+
+            let content = PictureInfo::read_from_db_and_s3(&pool, &permits)
+                .await
+                .unwrap();
+            assert_eq!(content.len(), 1, "round {}", round);
+            assert_eq!(content[0].1, picture, "round {}", round);
+            let (_, objects) = PictureInfo::delete_for_item(&pool, new).await.unwrap();
+            PictureInfo::delete_unused_objects(&pool, &permits, objects).await;
+        }
     }
 }
@@ -0,0 +1,79 @@
+use std::{future::Future, time::Duration};
+
+use anyhow::Result;
+use log::warn;
+
+/// Tells whether an error comes from a hiccup in reaching the database rather than from the query
+fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed)
+    )
+}
+
+/// Runs `f` until it succeeds, fails with a non-transient error or has used up `attempts`
+pub async fn retry_transient<T, F, Fut>(mut f: F, attempts: u32) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(error) if attempt < attempts && is_transient(&error) => {
+                warn!(
+                    "Transient database error on attempt {} of {}: {}",
+                    attempt, attempts, error
+                );
+                tokio::time::sleep(Duration::from_millis(50 * attempt as u64)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    async fn flaky(calls: &AtomicU32, failures: u32, error: fn() -> sqlx::Error) -> Result<u32> {
+        let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        if call <= failures {
+            return Err(error().into());
+        }
+        Ok(call)
+    }
+
+    #[tokio::test]
+    pub async fn retries_transient_errors() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_transient(|| flaky(&calls, 2, || sqlx::Error::PoolTimedOut), 3).await;
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    pub async fn gives_up_after_attempts() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_transient(|| flaky(&calls, 5, || sqlx::Error::PoolTimedOut), 3).await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    pub async fn does_not_retry_logical_errors() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_transient(|| flaky(&calls, 5, || sqlx::Error::RowNotFound), 3).await;
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,29 +1,337 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use axum::{
-    extract::{Path, Request, State},
-    http::StatusCode,
+    body::{Body, Bytes, HttpBody},
+    extract::{DefaultBodyLimit, Multipart, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
     middleware::{self, Next},
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{delete, get, post, put},
     Json, Router,
 };
-use log::info;
-use sqlx::PgPool;
-use tokio::time::Instant;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::{PgConnection, PgPool};
+use tokio::{sync::Semaphore, time::Instant};
 use tower::ServiceBuilder;
-use tower_http::trace::TraceLayer;
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    trace::TraceLayer,
+};
 
 use crate::{
-    category::{Category, NewCategory},
+    audit::{self, Action, AuditEntry},
+    category::{self, Category, NewCategory},
+    config::Config,
     error::HandlerError,
-    item::{Item, NewItem},
-    location::{Location, NewLocation},
-    picture::PictureInfo,
+    extractor::{JsonBody, PathParam},
+    integrity::IntegrityReport,
+    item::{
+        self, CategoryCoverage, Item, ItemCard, ItemWithPictureCount, ItemWithThumbnail,
+        MonthCount, NewItem,
+    },
+    location::{
+        Location, LocationExport, LocationItemCount, LocationNode, NewLocation, Occupancy,
+        Received, RecentlyActiveLocation,
+    },
+    note::{NewNote, Note},
+    picture::{
+        self, Moved, ObjectPage, PictureInfo, PictureUrl, S3Permits, StorageUsage, CSV_HEADER,
+    },
+    retry::retry_transient,
+    settings::Setting,
+    shutdown::track_in_flight,
+    state::AppState,
+    upsert::Upserted,
+    validation::{self, Truncation},
 };
 
-pub async fn profile_endpoint(request: Request, next: Next) -> Response {
+/// Formats headers for logging, replacing the values of `redacted` headers with `***`
+pub fn redact_headers(headers: &HeaderMap, redacted: &[String]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if redacted.iter().any(|header| header == name.as_str()) {
+                "***"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Number of requests seen by [`profile_endpoint`], sampled or not
+#[derive(Debug, Clone, Default)]
+pub struct RequestCounter(Arc<AtomicU64>);
+
+impl RequestCounter {
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What [`profile_endpoint`] reads, kept apart from [`AppState`] so it needs no database
+#[derive(Debug, Clone)]
+pub struct Profiling {
+    pub config: Arc<Config>,
+    pub requests: RequestCounter,
+}
+
+/// Slots for requests being handled, shared by every clone
+#[derive(Debug, Clone)]
+pub struct RequestPermits {
+    semaphore: Option<Arc<Semaphore>>,
+    queue_timeout: Duration,
+}
+
+impl RequestPermits {
+    /// Allows `limit` requests at once, or any number when it is 0
+    pub fn new(limit: usize, queue_timeout: Duration) -> Self {
+        Self {
+            semaphore: (limit > 0).then(|| Arc::new(Semaphore::new(limit))),
+            queue_timeout,
+        }
+    }
+}
+
+/// Holds each request until a slot is free, shedding it with 503 if none frees up in time.
+/// Status probes are never held back
+pub async fn limit_concurrency(
+    State(permits): State<RequestPermits>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(semaphore) = permits.semaphore else {
+        return next.run(request).await;
+    };
+    if request.uri().path().starts_with("/status/") {
+        return next.run(request).await;
+    }
+    match tokio::time::timeout(permits.queue_timeout, semaphore.acquire_owned()).await {
+        Ok(Ok(_permit)) => next.run(request).await,
+        _ => HandlerError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many requests in progress".to_string(),
+        )
+        .into_response(),
+    }
+}
+
+/// Answers with 504 when a request outlasts the timeout configured for its path. Multipart
+/// uploads are exempt, as cutting one short between storing an object and committing its row
+/// would leave the object behind
+pub async fn enforce_timeout(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/"));
+    if multipart {
+        return next.run(request).await;
+    }
+    let Some(timeout) = config.timeout_for(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => HandlerError::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("Request timed out after {} ms", timeout.as_millis()),
+        )
+        .into_response(),
+    }
+}
+
+/// Largest response re-encoded by [`camel_case_json`] or [`pretty_json`]. Larger responses, and
+/// streamed ones of unknown size, are passed through unchanged
+const MAX_REWRITTEN_RESPONSE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Turns `date_origin` into `dateOrigin`
+fn to_camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+/// Turns `dateOrigin` into `date_origin`, leaving snake_case keys as they are
+fn to_snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_uppercase() {
+            snake.push('_');
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Renames the keys of every object in a JSON value
+fn rename_keys(value: serde_json::Value, rename: fn(&str) -> String) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .map(|(key, value)| (rename(&key), rename_keys(value, rename)))
+            .collect(),
+        serde_json::Value::Array(values) => values
+            .into_iter()
+            .map(|value| rename_keys(value, rename))
+            .collect(),
+        value => value,
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// Re-encodes a JSON body with `rewrite`, leaving bodies that are not JSON untouched
+fn rewrite_json(
+    headers: &mut HeaderMap,
+    bytes: Bytes,
+    rewrite: impl FnOnce(serde_json::Value) -> String,
+) -> Body {
+    match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) => {
+            headers.remove(header::CONTENT_LENGTH);
+            Body::from(rewrite(value))
+        }
+        Err(_) => Body::from(bytes),
+    }
+}
+
+/// Re-encodes a JSON request body, rejecting bodies over `limit` bytes with 413
+async fn rewrite_json_request(
+    headers: &mut HeaderMap,
+    body: Body,
+    limit: usize,
+    rewrite: impl FnOnce(serde_json::Value) -> String,
+) -> Result<Body, HandlerError> {
+    let bytes = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+    Ok(rewrite_json(headers, bytes, rewrite))
+}
+
+/// Re-encodes a JSON response whose size is known and within [`MAX_REWRITTEN_RESPONSE_BYTES`],
+/// passing any other response through unchanged
+async fn rewrite_json_response(
+    response: Response,
+    rewrite: impl FnOnce(serde_json::Value) -> String,
+) -> Result<Response, HandlerError> {
+    let (mut parts, body) = response.into_parts();
+    let fits = body
+        .size_hint()
+        .exact()
+        .is_some_and(|size| size <= MAX_REWRITTEN_RESPONSE_BYTES);
+    if !is_json(&parts.headers) || !fits {
+        return Ok(Response::from_parts(parts, body));
+    }
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let body = rewrite_json(&mut parts.headers, bytes, rewrite);
+    Ok(Response::from_parts(parts, body))
+}
+
+/// When camelCase is configured, accepts camelCase or snake_case keys in JSON requests and
+/// answers with camelCase keys
+pub async fn camel_case_json(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HandlerError> {
+    if !config.camel_case {
+        return Ok(next.run(request).await);
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let body = if is_json(&parts.headers) {
+        rewrite_json_request(
+            &mut parts.headers,
+            body,
+            config.max_json_body_bytes,
+            |value| rename_keys(value, to_snake_case).to_string(),
+        )
+        .await?
+    } else {
+        body
+    };
+    let response = next.run(Request::from_parts(parts, body)).await;
+    rewrite_json_response(response, |value| {
+        rename_keys(value, to_camel_case).to_string()
+    })
+    .await
+}
+
+/// Query parameter asking for indented JSON, understood on every route
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PrettyJson {
+    #[serde(default)]
+    pretty: bool,
+}
+
+/// Indents JSON responses when `pretty=true` is passed, they stay compact otherwise
+pub async fn pretty_json(request: Request, next: Next) -> Result<Response, HandlerError> {
+    let Query(params) = Query::<PrettyJson>::try_from_uri(request.uri())
+        .map_err(|e| HandlerError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    let response = next.run(request).await;
+    if !params.pretty {
+        return Ok(response);
+    }
+    rewrite_json_response(response, |value| format!("{:#}", value)).await
+}
+
+/// Counts every request and logs the details of one in `log_sample_rate` of them. Slow requests
+/// are always warned about
+pub async fn profile_endpoint(
+    State(Profiling { config, requests }): State<Profiling>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let seen = requests.0.fetch_add(1, Ordering::Relaxed);
+    let sampled = seen % config.log_sample_rate.get() == 0;
+
     let method = request.method().clone().to_string();
     let uri = request.uri().clone();
-    info!("Handling {} at {}", method, uri);
+    if sampled {
+        debug!(
+            "Handling {} at {} with headers {}",
+            method,
+            uri,
+            redact_headers(request.headers(), &config.redacted_headers)
+        );
+    }
 
     let now = Instant::now();
 
@@ -31,628 +339,5983 @@ pub async fn profile_endpoint(request: Request, next: Next) -> Response {
 
     let elapsed = now.elapsed();
 
-    info!(
-        "Finished handling {} at {}, used {} ms",
-        method,
-        uri,
-        elapsed.as_millis()
-    );
+    if elapsed.as_millis() > config.slow_request_ms as u128 {
+        warn!(
+            "Slow request {} at {}, used {} ms",
+            method,
+            uri,
+            elapsed.as_millis()
+        );
+    } else if sampled {
+        debug!(
+            "Finished handling {} at {}, used {} ms",
+            method,
+            uri,
+            elapsed.as_millis()
+        );
+    }
+    response
+}
+
+/// Lets successful API reads be cached for the configured time and forbids caching writes
+pub async fn cache_control(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let is_api = request.uri().path().starts_with("/api/");
+
+    let mut response = next.run(request).await;
+
+    if !is_api || response.headers().contains_key(header::CACHE_CONTROL) {
+        return response;
+    }
+    let value = match method {
+        Method::GET | Method::HEAD if response.status().is_success() => {
+            format!("max-age={}", config.cache_max_age_secs)
+        }
+        Method::GET | Method::HEAD => return response,
+        _ => "no-store".to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
     response
 }
 
-pub fn create_router(connection: PgPool) -> Router {
+/// How much of the service is usable, as reported by the readiness endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessStatus {
+    Healthy,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    pub db: bool,
+    pub s3: bool,
+}
+
+/// How long a dependency gets to answer a readiness probe
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest page returned by the paginated list endpoints
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Query parameters for paginated list endpoints
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct Pagination {
+    #[serde(default, deserialize_with = "non_negative")]
+    limit: Option<i64>,
+    #[serde(default, deserialize_with = "non_negative")]
+    offset: Option<i64>,
+    #[serde(default)]
+    envelope: bool,
+}
+
+/// Reads an optional count, rejecting negative values so the query is answered with 400
+fn non_negative<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<i64>::deserialize(deserializer)?;
+    match value {
+        Some(value) if value < 0 => Err(serde::de::Error::custom(format!(
+            "limit and offset must not be negative, got {}",
+            value
+        ))),
+        _ => Ok(value),
+    }
+}
+
+impl Pagination {
+    /// Requested page size, falling back to the configured default and capped at
+    /// [`MAX_PAGE_SIZE`]
+    pub fn limit(&self, config: &Config) -> i64 {
+        self.limit
+            .unwrap_or(config.default_page_size)
+            .min(MAX_PAGE_SIZE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0)
+    }
+
+    /// Wraps a page of results in an [`Envelope`] if requested, only awaiting `total` then
+    pub async fn into_page<T, F>(
+        self,
+        config: &Config,
+        data: Vec<T>,
+        total: F,
+    ) -> Result<Page<T>, HandlerError>
+    where
+        F: Future<Output = anyhow::Result<i64>>,
+    {
+        if !self.envelope {
+            return Ok(Page::Bare(data));
+        }
+        let total = total
+            .await
+            .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        Ok(Page::Envelope(Envelope {
+            data,
+            total,
+            limit: self.limit(config),
+            offset: self.offset(),
+        }))
+    }
+}
+
+/// A page of results together with pagination metadata
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Envelope<T> {
+    pub data: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// Response of list endpoints, either a bare array or an [`Envelope`]
+#[derive(Debug)]
+pub enum Page<T> {
+    Bare(Vec<T>),
+    Envelope(Envelope<T>),
+}
+
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        match self {
+            Page::Bare(data) => Json(data).into_response(),
+            Page::Envelope(envelope) => Json(envelope).into_response(),
+        }
+    }
+}
+
+/// Query parameters asking for item listings to carry thumbnail links
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Thumbnails {
+    #[serde(default)]
+    with_thumbnails: bool,
+}
+
+/// Query parameters limiting item listings to a range of values, in cents
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct ValueRange {
+    min_value: Option<i64>,
+    max_value: Option<i64>,
+}
+
+impl ValueRange {
+    /// Whether any bound is given
+    fn is_set(&self) -> bool {
+        self.min_value.is_some() || self.max_value.is_some()
+    }
+
+    fn validate(&self) -> Result<(), HandlerError> {
+        if let (Some(min_value), Some(max_value)) = (self.min_value, self.max_value) {
+            if min_value > max_value {
+                return Err(HandlerError::new(
+                    StatusCode::BAD_REQUEST,
+                    "`min_value` must not be greater than `max_value`".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Query parameters selecting which fields of a resource to return
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Projection {
+    fields: Option<String>,
+}
+
+impl Projection {
+    /// Requested fields, rejecting any that are not in `allowed`
+    pub fn fields(
+        &self,
+        allowed: &[&'static str],
+    ) -> Result<Option<Vec<&'static str>>, HandlerError> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+        fields
+            .split(',')
+            .map(|field| {
+                allowed
+                    .iter()
+                    .find(|allowed| **allowed == field.trim())
+                    .copied()
+                    .ok_or_else(|| {
+                        HandlerError::new(
+                            StatusCode::BAD_REQUEST,
+                            format!("Unknown field `{}`", field.trim()),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some)
+    }
+}
+
+/// Query parameters for deleting a category
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct DeleteCategoryParams {
+    reassign_to: Option<i32>,
+}
+
+/// Query parameters for deleting a location
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct DeleteLocationParams {
+    #[serde(default)]
+    force: bool,
+}
+
+/// Query parameters for listing the objects of a bucket
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ObjectListing {
+    bucket: String,
+    token: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Query parameters for moving a picture to another item
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct MovePicture {
+    to_item: i32,
+}
+
+/// Query parameters for search endpoints
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Search {
+    q: String,
+}
+
+/// Query parameters for endpoints returning the first few results of a ranking
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct LimitQuery {
+    limit: Option<i64>,
+}
+
+impl LimitQuery {
+    /// Requested number of results, falling back to `default` and capped at `max`. Fails with
+    /// 400 below 1
+    pub fn limit(&self, default: i64, max: i64) -> Result<i64, HandlerError> {
+        let limit = self.limit.unwrap_or(default);
+        if limit < 1 {
+            return Err(HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                "Limit must be at least 1".to_string(),
+            ));
+        }
+        Ok(limit.min(max))
+    }
+}
+
+/// Results returned by the ranking endpoints when no limit is given
+const DEFAULT_RANKING_LIMIT: i64 = 10;
+
+/// Body of a request storing a setting
+#[derive(Deserialize, Debug, Clone)]
+pub struct SettingValue {
+    value: String,
+}
+
+/// Query parameters for picking a random item
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RandomItem {
+    category_id: Option<i32>,
+}
+
+/// Query parameters for exporting picture metadata
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PictureExport {
+    item_id: Option<i32>,
+}
+
+/// Body of a request exchanging the listing positions of two items
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwapOrder {
+    first: i32,
+    second: i32,
+}
+
+/// Query parameters for resolving a scanned label
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Scan {
+    code: String,
+}
+
+/// Query parameters for QR code labels
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct QrSize {
+    size: Option<u32>,
+}
+
+impl QrSize {
+    const DEFAULT: u32 = 256;
+    const BOUNDS: std::ops::RangeInclusive<u32> = 64..=1024;
+
+    /// Requested size in pixels, rejecting sizes outside [`QrSize::BOUNDS`]
+    pub fn size(&self) -> Result<u32, HandlerError> {
+        let size = self.size.unwrap_or(Self::DEFAULT);
+        if !Self::BOUNDS.contains(&size) {
+            return Err(HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Size must be between {} and {} pixels",
+                    Self::BOUNDS.start(),
+                    Self::BOUNDS.end()
+                ),
+            ));
+        }
+        Ok(size)
+    }
+}
+
+/// Query parameters for endpoints that can include the items of descendants
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Recursive {
+    #[serde(default)]
+    recursive: bool,
+}
+
+/// Allows cross-origin requests only from the configured origins
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let origins = origins
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect::<Vec<_>>();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(Any)
+        .allow_headers(Any)
+}
+
+/// Query parameters selecting the audited entity
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuditQuery {
+    entity: String,
+    id: i32,
+}
+
+pub fn create_router(state: AppState) -> Router {
+    let cors = cors_layer(&state.config().cors_origins);
+    // axum's own 2 MiB default would otherwise still cap bodies below a larger configured limit
+    let json_limit = (
+        DefaultBodyLimit::disable(),
+        RequestBodyLimitLayer::new(state.config().max_json_body_bytes),
+    );
+    let in_flight = state.in_flight.clone();
+    let permits = state.permits.clone();
+    let middleware_state = state.clone();
     Router::new()
         .route("/status/health", get(status))
+        .route("/status/ready", get(readiness))
+        .route("/status/requests", get(request_count))
         .route("/api/items", get(get_all_items))
         .route("/api/items/:user_id", get(get_item_by_id))
-        .route("/api/items", post(add_item))
+        .route("/api/items", post(add_item).layer(json_limit))
         .route("/api/items/:user_id", delete(delete_item_by_id))
-        .route("/api/items", put(update_item))
+        .route("/api/items", put(update_item).layer(json_limit))
+        .route("/api/items/by-month", get(get_item_counts_by_month))
+        .route("/api/items/random", get(get_random_item))
+        .route(
+            "/api/items/swap-order",
+            post(swap_item_order).layer(json_limit),
+        )
+        .route("/api/items/export.jsonl", get(export_items_as_json_lines))
+        .route("/api/items/top-value", get(get_top_value_items))
+        .route("/api/items/untouched", get(get_untouched_items))
+        .route(
+            "/api/items/most-photographed",
+            get(get_most_photographed_items),
+        )
+        .route(
+            "/api/items/category-coverage",
+            get(get_item_category_coverage),
+        )
+        .route(
+            "/api/items/with-picture-counts",
+            get(get_items_with_picture_counts),
+        )
+        .route(
+            "/api/items/by-ref/:external_ref",
+            get(get_item_by_external_ref),
+        )
+        .route("/api/items/:user_id/card", get(get_item_card))
+        .route("/api/items/:user_id/similar", get(get_similar_items))
+        .route("/api/items/:user_id/qr.png", get(get_item_qr_code))
+        .route("/api/items/:user_id/notes", get(get_item_notes))
+        .route(
+            "/api/items/:user_id/notes",
+            post(add_item_note).layer(json_limit),
+        )
+        .route("/api/notes/:user_id", delete(delete_note_by_id))
+        .route(
+            "/api/items/:user_id/recount-pictures",
+            post(recount_item_pictures),
+        )
+        .route(
+            "/api/items/:user_id/pictures/batch",
+            post(add_pictures_to_item),
+        )
+        .route("/api/items/:user_id/pictures", delete(delete_item_pictures))
+        .route(
+            "/api/items/:user_id/pictures/urls",
+            get(get_item_picture_urls),
+        )
         .route("/api/locations", get(get_all_locations))
         .route("/api/locations/:user_id", get(get_location_by_id))
-        .route("/api/locations", post(add_location))
+        .route("/api/locations", post(add_location).layer(json_limit))
         .route("/api/locations/:user_id", delete(delete_location_by_id))
-        .route("/api/locations", put(update_location))
+        .route("/api/locations", put(update_location).layer(json_limit))
+        .route(
+            "/api/locations/upsert",
+            put(upsert_location).layer(json_limit),
+        )
+        .route("/api/locations/item-counts", get(get_location_item_counts))
+        .route(
+            "/api/locations/recently-active",
+            get(get_recently_active_locations),
+        )
+        .route("/api/locations/:user_id/search", get(search_locations))
+        .route("/api/locations/:user_id/items", get(get_location_items))
+        .route("/api/locations/:user_id/tree", get(get_location_tree))
+        .route("/api/locations/:user_id/export", get(export_location))
+        .route(
+            "/api/locations/:user_id/receive",
+            post(receive_items_at_location).layer(json_limit),
+        )
+        .route(
+            "/api/locations/:user_id/occupancy",
+            get(get_location_occupancy),
+        )
         .route("/api/categories", get(get_all_categories))
         .route("/api/categories/:user_id", get(get_category_by_id))
-        .route("/api/categories", post(add_category))
+        .route("/api/categories", post(add_category).layer(json_limit))
         .route("/api/categories/:user_id", delete(delete_category_by_id))
-        .route("/api/categories", put(update_category))
+        .route("/api/categories", put(update_category).layer(json_limit))
+        .route("/api/categories/empty", get(get_empty_categories))
+        .route(
+            "/api/categories/ensure",
+            put(ensure_category).layer(json_limit),
+        )
+        .route("/api/categories/:user_id/path", get(get_category_path))
+        .route(
+            "/api/categories/:user_id/recent",
+            get(get_recent_category_items),
+        )
         .route("/api/pictures", get(get_all_pictures))
-        .with_state(connection)
+        .route("/api/pictures.csv", get(export_pictures_as_csv))
+        .route("/api/pictures/:user_id/move", post(move_picture))
+        .route("/api/storage/usage", get(get_storage_usage))
+        .route("/api/audit", get(get_audit_log))
+        .route("/api/admin/integrity", get(get_integrity_report))
+        .route("/api/admin/broken-pictures", get(get_broken_pictures))
+        .route("/api/admin/s3/objects", get(get_s3_objects))
+        .route("/api/admin/recount-pictures", post(recount_all_pictures))
+        .route("/api/admin/settings/reload", post(reload_settings))
+        .route("/api/admin/settings/:key", get(get_setting))
+        .route(
+            "/api/admin/settings/:key",
+            put(put_setting).layer(json_limit),
+        )
+        .route("/api/scan", get(scan_item))
+        .with_state(state)
         .layer(
             ServiceBuilder::new()
+                .layer(middleware::from_fn_with_state(in_flight, track_in_flight))
+                .layer(middleware::from_fn_with_state(permits, limit_concurrency))
+                .layer(middleware::from_fn_with_state(
+                    middleware_state.clone(),
+                    enforce_timeout,
+                ))
                 .layer(TraceLayer::new_for_http())
-                .layer(middleware::from_fn(profile_endpoint)),
+                .layer(cors)
+                .layer(middleware::from_fn_with_state(
+                    middleware_state.clone(),
+                    cache_control,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    middleware_state.clone(),
+                    profile_endpoint,
+                ))
+                .layer(middleware::from_fn(pretty_json))
+                .layer(middleware::from_fn_with_state(
+                    middleware_state,
+                    camel_case_json,
+                )),
         )
 }
 
-async fn status() -> (StatusCode, String) {
-    (StatusCode::OK, "Healthy".to_string())
+/// Records a mutation of an entity in the audit log
+async fn audit(
+    connection: &mut PgConnection,
+    entity: &str,
+    entity_id: i32,
+    action: Action,
+    diff: Option<serde_json::Value>,
+) -> Result<(), HandlerError> {
+    AuditEntry::record(
+        connection,
+        entity,
+        entity_id,
+        action,
+        audit::ANONYMOUS,
+        diff,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
 }
 
-async fn get_all_items(State(connection): State<PgPool>) -> Result<Json<Vec<Item>>, HandlerError> {
-    let items = Item::read_from_db(&connection)
-        .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(items))
+/// Parses an HTTP date in any of the three formats of RFC 9110: the preferred
+/// `Sun, 06 Nov 1994 08:49:37 GMT`, RFC 850's `Sunday, 06-Nov-94 08:49:37 GMT` and asctime's
+/// `Sun Nov  6 08:49:37 1994`
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+    ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|date| date.and_utc())
 }
 
-async fn get_item_by_id(
-    State(connection): State<PgPool>,
-    Path(item_id): Path<i32>,
-) -> Result<Json<Item>, HandlerError> {
-    let item = Item::read_from_db_by_id(&connection, item_id)
-        .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(item))
+/// Reads the client's `If-Unmodified-Since`, answering dates that do not parse with 400
+fn unmodified_since(headers: &HeaderMap) -> Result<Option<DateTime<Utc>>, HandlerError> {
+    let Some(value) = headers.get(header::IF_UNMODIFIED_SINCE) else {
+        return Ok(None);
+    };
+    let since = value
+        .to_str()
+        .ok()
+        .and_then(parse_http_date)
+        .ok_or_else(|| {
+            HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                "`If-Unmodified-Since` must be an HTTP date".to_string(),
+            )
+        })?;
+    Ok(Some(since))
 }
 
-async fn add_item(
-    State(connection): State<PgPool>,
-    Json(payload): Json<NewItem>,
-) -> Result<(), HandlerError> {
-    Item::insert_into_db(
-        &connection,
-        &payload.name,
-        &payload.description,
-        payload.date_origin,
-    )
-    .await
-    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// Answers an update with 412 when the row was left alone because it changed after `since`.
+/// HTTP dates have whole seconds, so the updates only compare whole seconds
+fn ensure_updated(updated: bool, since: Option<DateTime<Utc>>) -> Result<(), HandlerError> {
+    match since {
+        Some(since) if !updated => Err(HandlerError::new(
+            StatusCode::PRECONDITION_FAILED,
+            format!("Modified since {}", since.to_rfc2822()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Answers a delete with 404 when there was no `entity` with the id
+fn ensure_deleted(deleted: bool, entity: &str, id: i32) -> Result<(), HandlerError> {
+    if !deleted {
+        return Err(HandlerError::new(
+            StatusCode::NOT_FOUND,
+            format!("No {} with id {}", entity, id),
+        ));
+    }
     Ok(())
 }
 
-async fn delete_item_by_id(
-    State(connection): State<PgPool>,
-    Path(item_id): Path<i32>,
-) -> Result<(), HandlerError> {
-    Item::delete_from_db(&connection, item_id)
-        .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// Rejects requests needing object storage with 503 when it was not configured at startup
+fn ensure_storage(config: &Config) -> Result<(), HandlerError> {
+    if !config.storage_configured {
+        return Err(HandlerError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "File storage not configured".to_string(),
+        ));
+    }
     Ok(())
 }
 
-async fn update_item(
-    State(connection): State<PgPool>,
-    Json(item): Json<Item>,
+/// Rejects placing one more item in a location that is already full. The location stays locked
+/// until the transaction of `connection` ends
+async fn ensure_capacity(
+    connection: &mut PgConnection,
+    location_id: i32,
 ) -> Result<(), HandlerError> {
-    Item::update_in_db(&connection, &item)
+    let occupancy = Location::lock_occupancy(connection, location_id)
         .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(())
+        .map_err(HandlerError::from_db)?;
+    match occupancy.remaining {
+        Some(remaining) if remaining < 1 => Err(HandlerError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Location {} is full, it holds {} of {} items",
+                location_id,
+                occupancy.used,
+                occupancy.capacity.unwrap_or_default()
+            ),
+        )),
+        _ => Ok(()),
+    }
 }
 
-async fn get_all_locations(
-    State(connection): State<PgPool>,
-) -> Result<Json<Vec<Location>>, HandlerError> {
-    let locations = Location::read_from_db(&connection)
-        .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(locations))
+async fn status() -> (StatusCode, String) {
+    (StatusCode::OK, "Healthy".to_string())
 }
 
-async fn get_location_by_id(
-    State(connection): State<PgPool>,
-    Path(location_id): Path<i32>,
-) -> Result<Json<Location>, HandlerError> {
-    let location = Location::read_from_db_by_id(&connection, location_id)
-        .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(location))
+async fn request_count(State(requests): State<RequestCounter>) -> Json<u64> {
+    Json(requests.count())
 }
 
-async fn add_location(
+async fn readiness(
     State(connection): State<PgPool>,
-    Json(payload): Json<NewLocation>,
-) -> Result<(), HandlerError> {
-    Location::insert_into_db(&connection, &payload.name, &payload.description)
+    State(s3_permits): State<S3Permits>,
+) -> (StatusCode, Json<Readiness>) {
+    let db = tokio::time::timeout(
+        READINESS_TIMEOUT,
+        sqlx::query("SELECT 1").execute(&connection),
+    )
+    .await
+    .is_ok_and(|result| result.is_ok());
+    let s3 = tokio::time::timeout(READINESS_TIMEOUT, PictureInfo::ping_s3(&s3_permits))
         .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(())
+        .is_ok_and(|result| result.is_ok());
+
+    let (code, status) = match (db, s3) {
+        (true, true) => (StatusCode::OK, ReadinessStatus::Healthy),
+        (true, false) => (StatusCode::OK, ReadinessStatus::Degraded),
+        (false, _) => (StatusCode::SERVICE_UNAVAILABLE, ReadinessStatus::Down),
+    };
+    (code, Json(Readiness { status, db, s3 }))
 }
 
-async fn delete_location_by_id(
+async fn get_all_items(
     State(connection): State<PgPool>,
-    Path(location_id): Path<i32>,
-) -> Result<(), HandlerError> {
-    Location::delete_from_db(&connection, location_id)
+    State(config): State<Arc<Config>>,
+    Query(pagination): Query<Pagination>,
+    Query(projection): Query<Projection>,
+    Query(thumbnails): Query<Thumbnails>,
+    Query(value_range): Query<ValueRange>,
+) -> Result<Response, HandlerError> {
+    if value_range.is_set() {
+        value_range.validate()?;
+        if thumbnails.with_thumbnails || projection.fields(item::PROJECTABLE_FIELDS)?.is_some() {
+            return Err(HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                "`min_value` and `max_value` cannot be combined with `fields` or `with_thumbnails`"
+                    .to_string(),
+            ));
+        }
+        let items = retry_transient(
+            || {
+                Item::read_in_value_range(
+                    &connection,
+                    value_range.min_value,
+                    value_range.max_value,
+                    pagination.limit(&config),
+                    pagination.offset(),
+                )
+            },
+            config.db_read_attempts,
+        )
         .await
         .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(())
-}
-
-async fn update_location(
-    State(connection): State<PgPool>,
-    Json(location): Json<Location>,
-) -> Result<(), HandlerError> {
-    Location::update_in_db(&connection, &location)
+        return Ok(pagination
+            .into_page(
+                &config,
+                items,
+                retry_transient(
+                    || {
+                        Item::count_in_value_range(
+                            &connection,
+                            value_range.min_value,
+                            value_range.max_value,
+                        )
+                    },
+                    config.db_read_attempts,
+                ),
+            )
+            .await?
+            .into_response());
+    }
+    if thumbnails.with_thumbnails {
+        ensure_storage(&config)?;
+        if projection.fields(item::PROJECTABLE_FIELDS)?.is_some() {
+            return Err(HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                "`fields` cannot be combined with `with_thumbnails`".to_string(),
+            ));
+        }
+        let items: Vec<ItemWithThumbnail> = retry_transient(
+            || {
+                Item::read_with_thumbnails(
+                    &connection,
+                    pagination.limit(&config),
+                    pagination.offset(),
+                    config.presign_expiry_secs,
+                )
+            },
+            config.db_read_attempts,
+        )
         .await
         .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(())
-}
-
-async fn get_all_categories(
-    State(connection): State<PgPool>,
-) -> Result<Json<Vec<Category>>, HandlerError> {
-    let categories = Category::read_from_db(&connection)
+        return Ok(pagination
+            .into_page(
+                &config,
+                items,
+                retry_transient(|| Item::count(&connection), config.db_read_attempts),
+            )
+            .await?
+            .into_response());
+    }
+    if let Some(fields) = projection.fields(item::PROJECTABLE_FIELDS)? {
+        let items = retry_transient(
+            || {
+                Item::read_projected_from_db(
+                    &connection,
+                    &fields,
+                    pagination.limit(&config),
+                    pagination.offset(),
+                )
+            },
+            config.db_read_attempts,
+        )
         .await
         .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(categories))
+        return Ok(pagination
+            .into_page(
+                &config,
+                items,
+                retry_transient(|| Item::count(&connection), config.db_read_attempts),
+            )
+            .await?
+            .into_response());
+    }
+
+    let items = retry_transient(
+        || Item::read_from_db(&connection, pagination.limit(&config), pagination.offset()),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(pagination
+        .into_page(
+            &config,
+            items,
+            retry_transient(|| Item::count(&connection), config.db_read_attempts),
+        )
+        .await?
+        .into_response())
 }
 
-async fn get_category_by_id(
+async fn get_item_by_id(
     State(connection): State<PgPool>,
-    Path(category_id): Path<i32>,
-) -> Result<Json<Category>, HandlerError> {
-    let category = Category::read_from_db_by_id(&connection, category_id)
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+) -> Result<Json<Item>, HandlerError> {
+    let item = retry_transient(
+        || Item::read_from_db_by_id(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(item))
+}
+
+async fn get_item_by_external_ref(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(external_ref): PathParam<String>,
+) -> Result<Json<Item>, HandlerError> {
+    let item = retry_transient(
+        || Item::read_by_external_ref(&connection, &external_ref),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(item))
+}
+
+async fn add_item(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(truncation): Query<Truncation>,
+    JsonBody(mut payload): JsonBody<NewItem>,
+) -> Result<(HeaderMap, Json<Item>), HandlerError> {
+    payload.date_origin.get_or_insert_with(Utc::now);
+    let warning = validation::fit_name_and_description(
+        &config,
+        &payload.name,
+        &mut payload.description,
+        truncation.truncate,
+    )?;
+    let mut transaction = connection.begin().await?;
+    if let Some(location_id) = payload.location_id {
+        ensure_capacity(&mut transaction, location_id).await?;
+    }
+    let item_id = Item::insert_into_db(&mut *transaction, &payload)
+        .await
+        .map_err(HandlerError::from_db)?;
+    audit(
+        &mut transaction,
+        "item",
+        item_id,
+        Action::Create,
+        serde_json::to_value(&payload).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    let item = Item::read_from_db_by_id(&connection, item_id)
         .await
         .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(category))
+    Ok((warning, Json(item)))
 }
 
-async fn add_category(
+async fn get_random_item(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<RandomItem>,
+) -> Result<Json<Item>, HandlerError> {
+    let item = retry_transient(
+        || Item::random(&connection, params.category_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(item))
+}
+
+async fn swap_item_order(
     State(connection): State<PgPool>,
-    Json(payload): Json<NewCategory>,
+    JsonBody(swap): JsonBody<SwapOrder>,
 ) -> Result<(), HandlerError> {
-    Category::insert_into_db(&connection, &payload.name, &payload.description)
+    if swap.first == swap.second {
+        return Err(HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            "Cannot swap an item with itself".to_string(),
+        ));
+    }
+    let mut transaction = connection.begin().await?;
+    Item::swap_sort_order(&mut *transaction, swap.first, swap.second)
         .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(HandlerError::from_db)?;
+    for (item_id, other) in [(swap.first, swap.second), (swap.second, swap.first)] {
+        audit(
+            &mut transaction,
+            "item",
+            item_id,
+            Action::Update,
+            Some(serde_json::json!({ "sort_order_swapped_with": other })),
+        )
+        .await?;
+    }
+    transaction.commit().await?;
     Ok(())
 }
 
-async fn delete_category_by_id(
+async fn delete_item_by_id(
     State(connection): State<PgPool>,
-    Path(category_id): Path<i32>,
+    PathParam(item_id): PathParam<i32>,
 ) -> Result<(), HandlerError> {
-    Category::delete_from_db(&connection, category_id)
+    let mut transaction = connection.begin().await?;
+    let deleted = Item::delete_from_db(&mut *transaction, item_id)
         .await
-        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(HandlerError::from_db)?;
+    ensure_deleted(deleted, "item", item_id)?;
+    audit(&mut transaction, "item", item_id, Action::Delete, None).await?;
+    transaction.commit().await?;
     Ok(())
 }
 
-async fn update_category(
+async fn update_item(
     State(connection): State<PgPool>,
-    Json(category): Json<Category>,
-) -> Result<(), HandlerError> {
-    Category::update_in_db(&connection, &category)
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Query(truncation): Query<Truncation>,
+    JsonBody(mut item): JsonBody<Item>,
+) -> Result<HeaderMap, HandlerError> {
+    let warning = validation::fit_name_and_description(
+        &config,
+        &item.name,
+        &mut item.description,
+        truncation.truncate,
+    )?;
+    let since = unmodified_since(&headers)?;
+    let current = Item::read_from_db_by_id(&connection, item.id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    let mut transaction = connection.begin().await?;
+    if let Some(location_id) = item.location_id {
+        if current.location_id != Some(location_id) {
+            ensure_capacity(&mut transaction, location_id).await?;
+        }
+    }
+    let updated = Item::update_in_db(&mut *transaction, &item, since)
+        .await
+        .map_err(HandlerError::from_db)?;
+    ensure_updated(updated, since)?;
+    audit(
+        &mut transaction,
+        "item",
+        item.id,
+        Action::Update,
+        serde_json::to_value(&item).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(warning)
+}
+
+async fn get_item_card(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+) -> Result<Json<ItemCard>, HandlerError> {
+    ensure_storage(&config)?;
+    let card = retry_transient(
+        || Item::read_card(&connection, item_id, config.presign_expiry_secs),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(card))
+}
+
+async fn get_item_picture_urls(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+) -> Result<Json<Vec<PictureUrl>>, HandlerError> {
+    ensure_storage(&config)?;
+    retry_transient(
+        || Item::read_from_db_by_id(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let urls = retry_transient(
+        || PictureInfo::presigned_urls_for_item(&connection, item_id, config.presign_expiry_secs),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(urls))
+}
+
+/// Corrects the cached picture count of an item, returning the count
+async fn recount_item_pictures(
+    State(connection): State<PgPool>,
+    PathParam(item_id): PathParam<i32>,
+) -> Result<Json<i64>, HandlerError> {
+    let count = Item::recount_pictures(&connection, item_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    Ok(Json(count))
+}
+
+async fn delete_item_pictures(
+    State(connection): State<PgPool>,
+    State(s3_permits): State<S3Permits>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+) -> Result<Json<u64>, HandlerError> {
+    ensure_storage(&config)?;
+    let mut transaction = connection.begin().await?;
+    let (removed, objects) = PictureInfo::delete_for_item(&mut *transaction, item_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    audit(
+        &mut transaction,
+        "item",
+        item_id,
+        Action::Update,
+        Some(serde_json::json!({ "pictures_removed": removed })),
+    )
+    .await?;
+    transaction.commit().await?;
+    PictureInfo::delete_unused_objects(&connection, &s3_permits, objects).await;
+    Ok(Json(removed))
+}
+
+async fn get_item_notes(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+) -> Result<Json<Vec<Note>>, HandlerError> {
+    retry_transient(
+        || Item::read_from_db_by_id(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let notes = retry_transient(
+        || Note::read_for_item(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(notes))
+}
+
+async fn add_item_note(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+    JsonBody(payload): JsonBody<NewNote>,
+) -> Result<Json<Note>, HandlerError> {
+    validation::ensure_not_blank("body", &payload.body)?;
+    validation::ensure_max_len("body", &payload.body, config.max_description_len)?;
+    Item::read_from_db_by_id(&connection, item_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    let mut transaction = connection.begin().await?;
+    let note = Note::insert_into_db(&mut *transaction, item_id, &payload.body)
         .await
         .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(())
+    audit(
+        &mut transaction,
+        "note",
+        note.id,
+        Action::Create,
+        serde_json::to_value(&note).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(Json(note))
 }
 
-async fn get_all_pictures(
+async fn delete_note_by_id(
     State(connection): State<PgPool>,
-) -> Result<Json<Vec<PictureInfo>>, HandlerError> {
-    let pictures = PictureInfo::read_from_db(&connection)
+    PathParam(note_id): PathParam<i32>,
+) -> Result<(), HandlerError> {
+    let mut transaction = connection.begin().await?;
+    let deleted = Note::delete_from_db(&mut *transaction, note_id)
         .await
+        .map_err(HandlerError::from_db)?;
+    ensure_deleted(deleted, "note", note_id)?;
+    audit(&mut transaction, "note", note_id, Action::Delete, None).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn get_item_qr_code(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+    Query(params): Query<QrSize>,
+) -> Result<Response, HandlerError> {
+    let size = params.size()?;
+    retry_transient(
+        || Item::read_from_db_by_id(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let png = item::qr_code_png(item_id, size)
         .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(pictures))
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
 }
 
-#[cfg(test)]
-mod tests {
-    use sqlx::PgPool;
+async fn scan_item(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(scan): Query<Scan>,
+) -> Result<Json<Item>, HandlerError> {
+    let item_id = item::parse_scan_code(&scan.code).ok_or_else(|| {
+        HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Unrecognised code `{}`, expected `{}<id>` or an id",
+                scan.code,
+                item::SCAN_CODE_PREFIX
+            ),
+        )
+    })?;
+    let item = retry_transient(
+        || Item::read_from_db_by_id(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(item))
+}
 
-    use crate::{
-        category::{Category, NewCategory},
-        location::{Location, NewLocation},
-        router::create_router,
-    };
+async fn get_items_with_picture_counts(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Page<ItemWithPictureCount>, HandlerError> {
+    let items = retry_transient(
+        || {
+            Item::read_with_picture_counts(
+                &connection,
+                pagination.limit(&config),
+                pagination.offset(),
+            )
+        },
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    pagination
+        .into_page(
+            &config,
+            items,
+            retry_transient(|| Item::count(&connection), config.db_read_attempts),
+        )
+        .await
+}
 
-    #[sqlx::test]
-    pub async fn get_health(pool: PgPool) {
-        let router = create_router(pool);
+async fn get_top_value_items(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<LimitQuery>,
+) -> Result<Json<Vec<Item>>, HandlerError> {
+    let limit = params.limit(DEFAULT_RANKING_LIMIT, MAX_PAGE_SIZE)?;
+    let items = retry_transient(
+        || Item::top_by_value(&connection, limit),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(items))
+}
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-        let handle = tokio::spawn(async move {
-            axum::serve(listener, router).await.unwrap();
-        });
+/// Counts the categories spanned by the items the list endpoint would return for the same
+/// filters
+async fn get_item_category_coverage(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(value_range): Query<ValueRange>,
+) -> Result<Json<CategoryCoverage>, HandlerError> {
+    value_range.validate()?;
+    let coverage = retry_transient(
+        || Item::category_coverage(&connection, value_range.min_value, value_range.max_value),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(coverage))
+}
 
-        let client = reqwest::Client::new();
+/// Lists items still as they were created, for reviewing
+async fn get_untouched_items(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Page<Item>, HandlerError> {
+    let items = retry_transient(
+        || Item::read_untouched(&connection, pagination.limit(&config), pagination.offset()),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    pagination
+        .into_page(
+            &config,
+            items,
+            retry_transient(
+                || Item::count_untouched(&connection),
+                config.db_read_attempts,
+            ),
+        )
+        .await
+}
 
-        let response = client
-            .get("http://localhost:3000/status/health")
-            .send()
+async fn get_most_photographed_items(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<LimitQuery>,
+) -> Result<Json<Vec<ItemWithPictureCount>>, HandlerError> {
+    let limit = params.limit(DEFAULT_RANKING_LIMIT, MAX_PAGE_SIZE)?;
+    let items = retry_transient(
+        || Item::most_photographed(&connection, limit),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(items))
+}
+
+async fn get_recently_active_locations(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<LimitQuery>,
+) -> Result<Json<Vec<RecentlyActiveLocation>>, HandlerError> {
+    let limit = params.limit(DEFAULT_RANKING_LIMIT, MAX_PAGE_SIZE)?;
+    let locations = retry_transient(
+        || Location::recently_active(&connection, limit),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(locations))
+}
+
+async fn get_similar_items(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+    Query(params): Query<LimitQuery>,
+) -> Result<Json<Vec<Item>>, HandlerError> {
+    let limit = params.limit(DEFAULT_RANKING_LIMIT, MAX_PAGE_SIZE)?;
+    retry_transient(
+        || Item::read_from_db_by_id(&connection, item_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let items = retry_transient(
+        || Item::similar(&connection, item_id, limit),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(items))
+}
+
+/// Streams every item as one JSON object per line, reading rows from the database as they are sent
+async fn export_items_as_json_lines(State(connection): State<PgPool>) -> Response {
+    let (sender, receiver) = tokio::sync::mpsc::channel::<Result<String, axum::BoxError>>(64);
+    tokio::spawn(async move {
+        let mut items = Item::stream_from_db(&connection);
+        while let Some(item) = items.next().await {
+            let line = item
+                .map_err(axum::BoxError::from)
+                .and_then(|item| Ok(serde_json::to_string(&item)? + "\n"));
+            if sender.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+    let lines = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|line| (line, receiver))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+async fn export_pictures_as_csv(
+    State(connection): State<PgPool>,
+    Query(params): Query<PictureExport>,
+) -> Response {
+    let (sender, receiver) = tokio::sync::mpsc::channel::<Result<String, axum::BoxError>>(64);
+    tokio::spawn(async move {
+        if sender.send(Ok(CSV_HEADER.to_string())).await.is_err() {
+            return;
+        }
+        let mut pictures = PictureInfo::stream_from_db(&connection, params.item_id);
+        while let Some(picture) = pictures.next().await {
+            let row = picture
+                .map(|picture| picture.csv_row())
+                .map_err(axum::BoxError::from);
+            if sender.send(row).await.is_err() {
+                break;
+            }
+        }
+    });
+    let rows = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|row| (row, receiver))
+    });
+    (
+        [(header::CONTENT_TYPE, "text/csv")],
+        Body::from_stream(rows),
+    )
+        .into_response()
+}
+
+async fn get_item_counts_by_month(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Vec<MonthCount>>, HandlerError> {
+    let counts = retry_transient(
+        || Item::counts_by_month(&connection),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(counts))
+}
+
+/// Stores each part of a multipart body as a picture of the item, described by the part name
+async fn add_pictures_to_item(
+    State(connection): State<PgPool>,
+    State(s3_permits): State<S3Permits>,
+    State(config): State<Arc<Config>>,
+    PathParam(item_id): PathParam<i32>,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<i32>>, HandlerError> {
+    ensure_storage(&config)?;
+    Item::read_from_db_by_id(&connection, item_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+
+    let mut picture_ids = Vec::new();
+    while let Some(field) = multipart.next_field().await? {
+        let description = field.name().unwrap_or_default().to_string();
+        let picture = field.bytes().await?;
+        let mut transaction = connection.begin().await?;
+        let picture_id = PictureInfo::insert_into_db(
+            &mut *transaction,
+            &s3_permits,
+            item_id,
+            &description,
+            &picture,
+        )
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        audit(
+            &mut transaction,
+            "picture",
+            picture_id,
+            Action::Create,
+            Some(serde_json::json!({ "item_id": item_id, "description": description })),
+        )
+        .await?;
+        transaction.commit().await?;
+        picture_ids.push(picture_id);
+    }
+    Ok(Json(picture_ids))
+}
+
+async fn get_all_locations(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Page<Location>, HandlerError> {
+    let locations = retry_transient(
+        || Location::read_from_db(&connection, pagination.limit(&config), pagination.offset()),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    pagination
+        .into_page(
+            &config,
+            locations,
+            retry_transient(|| Location::count(&connection), config.db_read_attempts),
+        )
+        .await
+}
+
+async fn get_location_by_id(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(location_id): PathParam<i32>,
+) -> Result<Json<Location>, HandlerError> {
+    let location = retry_transient(
+        || Location::read_from_db_by_id(&connection, location_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(location))
+}
+
+async fn add_location(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(truncation): Query<Truncation>,
+    JsonBody(mut payload): JsonBody<NewLocation>,
+) -> Result<(HeaderMap, Json<Location>), HandlerError> {
+    let warning = validation::fit_name_and_description(
+        &config,
+        &payload.name,
+        &mut payload.description,
+        truncation.truncate,
+    )?;
+    validation::ensure_non_negative("capacity", payload.capacity)?;
+    let mut transaction = connection.begin().await?;
+    let location_id = Location::insert_into_db(
+        &mut *transaction,
+        &payload.name,
+        &payload.description,
+        payload.parent_id,
+        payload.capacity,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    audit(
+        &mut transaction,
+        "location",
+        location_id,
+        Action::Create,
+        serde_json::to_value(&payload).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    let location = Location::read_from_db_by_id(&connection, location_id)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((warning, Json(location)))
+}
+
+async fn delete_location_by_id(
+    State(connection): State<PgPool>,
+    PathParam(location_id): PathParam<i32>,
+    Query(params): Query<DeleteLocationParams>,
+) -> Result<(), HandlerError> {
+    let mut transaction = connection.begin().await?;
+    if !params.force {
+        Location::lock(&mut transaction, location_id)
             .await
-            .unwrap();
-        let body = response.text().await.unwrap();
-        assert_eq!(body, "Healthy");
+            .map_err(HandlerError::from_db)?;
+        let item_count = Location::count_items(&mut *transaction, location_id)
+            .await
+            .map_err(HandlerError::from_db)?;
+        if item_count > 0 {
+            return Err(HandlerError::new(
+                StatusCode::CONFLICT,
+                format!(
+                    "Location {} still holds {} items, pass force=true to leave them without a location",
+                    location_id, item_count
+                ),
+            ));
+        }
+    }
+    let deleted = Location::delete_from_db(&mut *transaction, location_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    ensure_deleted(deleted, "location", location_id)?;
+    audit(
+        &mut transaction,
+        "location",
+        location_id,
+        Action::Delete,
+        None,
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
 
-        handle.abort();
-        assert!(handle.await.is_err());
+async fn update_location(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Query(truncation): Query<Truncation>,
+    JsonBody(mut location): JsonBody<Location>,
+) -> Result<HeaderMap, HandlerError> {
+    let warning = validation::fit_name_and_description(
+        &config,
+        &location.name,
+        &mut location.description,
+        truncation.truncate,
+    )?;
+    validation::ensure_non_negative("capacity", location.capacity)?;
+    let since = unmodified_since(&headers)?;
+    if since.is_some() {
+        Location::read_from_db_by_id(&connection, location.id)
+            .await
+            .map_err(HandlerError::from_db)?;
     }
+    let mut transaction = connection.begin().await?;
+    let updated = Location::update_in_db(&mut *transaction, &location, since)
+        .await
+        .map_err(HandlerError::from_db)?;
+    ensure_updated(updated, since)?;
+    audit(
+        &mut transaction,
+        "location",
+        location.id,
+        Action::Update,
+        serde_json::to_value(&location).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(warning)
+}
 
-    #[sqlx::test]
-    pub async fn add_location(pool: PgPool) {
-        let router = create_router(pool);
+async fn upsert_location(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    JsonBody(payload): JsonBody<NewLocation>,
+) -> Result<Json<Upserted>, HandlerError> {
+    validation::ensure_name_and_description(&config, &payload.name, &payload.description)?;
+    let mut transaction = connection.begin().await?;
+    let upserted = Location::upsert_by_name(&mut *transaction, &payload.name, &payload.description)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let action = if upserted.created {
+        Action::Create
+    } else {
+        Action::Update
+    };
+    audit(
+        &mut transaction,
+        "location",
+        upserted.id,
+        action,
+        serde_json::to_value(&payload).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(Json(upserted))
+}
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
+/// Makes sure a category with the given name exists, creating it if needed
+async fn ensure_category(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    JsonBody(payload): JsonBody<NewCategory>,
+) -> Result<Json<Upserted>, HandlerError> {
+    validation::ensure_name_and_description(&config, &payload.name, &payload.description)?;
+    let mut transaction = connection.begin().await?;
+    let ensured = Category::ensure_by_name(
+        &mut *transaction,
+        &payload.name,
+        &payload.description,
+        payload.parent_id,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    if ensured.created {
+        audit(
+            &mut transaction,
+            "category",
+            ensured.id,
+            Action::Create,
+            serde_json::to_value(&payload).ok(),
+        )
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(Json(ensured))
+}
+
+async fn get_location_item_counts(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Vec<LocationItemCount>>, HandlerError> {
+    let counts = retry_transient(
+        || Location::item_counts(&connection),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(counts))
+}
+
+async fn search_locations(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(location_id): PathParam<i32>,
+    Query(search): Query<Search>,
+) -> Result<Json<Vec<Location>>, HandlerError> {
+    let locations = retry_transient(
+        || Location::search_in_subtree(&connection, location_id, &search.q),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(locations))
+}
+
+async fn get_location_items(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(location_id): PathParam<i32>,
+    Query(params): Query<Recursive>,
+) -> Result<Json<Vec<Item>>, HandlerError> {
+    retry_transient(
+        || Location::read_from_db_by_id(&connection, location_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let items = retry_transient(
+        || Location::read_items(&connection, location_id, params.recursive),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(items))
+}
+
+async fn get_location_tree(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(location_id): PathParam<i32>,
+) -> Result<Json<LocationNode>, HandlerError> {
+    let tree = retry_transient(
+        || Location::read_tree(&connection, location_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(tree))
+}
+
+async fn export_location(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(location_id): PathParam<i32>,
+) -> Result<Json<LocationExport>, HandlerError> {
+    let export = retry_transient(
+        || Location::export(&connection, location_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(export))
+}
+
+async fn receive_items_at_location(
+    State(connection): State<PgPool>,
+    PathParam(location_id): PathParam<i32>,
+    JsonBody(item_ids): JsonBody<Vec<i32>>,
+) -> Result<Json<u64>, HandlerError> {
+    let mut transaction = connection.begin().await?;
+    let moved = match Location::receive_items(&mut *transaction, location_id, &item_ids)
+        .await
+        .map_err(HandlerError::from_db)?
+    {
+        Received::Moved(moved) => moved,
+        Received::Full {
+            remaining,
+            arriving,
+        } => {
+            return Err(HandlerError::new(
+                StatusCode::CONFLICT,
+                format!(
+                    "Location {} has room for {} more items, {} would arrive",
+                    location_id, remaining, arriving
+                ),
+            ))
+        }
+    };
+    for &item_id in &moved {
+        audit(
+            &mut transaction,
+            "item",
+            item_id,
+            Action::Update,
+            Some(serde_json::json!({ "location_id": location_id })),
+        )
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(Json(moved.len() as u64))
+}
+
+async fn get_location_occupancy(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(location_id): PathParam<i32>,
+) -> Result<Json<Occupancy>, HandlerError> {
+    let occupancy = retry_transient(
+        || Location::occupancy(&connection, location_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(occupancy))
+}
+
+async fn get_all_categories(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Page<Category>, HandlerError> {
+    let categories = retry_transient(
+        || Category::read_from_db(&connection, pagination.limit(&config), pagination.offset()),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    pagination
+        .into_page(
+            &config,
+            categories,
+            retry_transient(|| Category::count(&connection), config.db_read_attempts),
+        )
+        .await
+}
+
+async fn get_empty_categories(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Vec<Category>>, HandlerError> {
+    let categories = retry_transient(
+        || Category::read_empty_from_db(&connection),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(categories))
+}
+
+async fn get_category_by_id(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(category_id): PathParam<i32>,
+) -> Result<Json<Category>, HandlerError> {
+    let category = retry_transient(
+        || Category::read_from_db_by_id(&connection, category_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(category))
+}
+
+async fn get_category_path(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(category_id): PathParam<i32>,
+) -> Result<Json<Vec<Category>>, HandlerError> {
+    retry_transient(
+        || Category::read_from_db_by_id(&connection, category_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let path = retry_transient(
+        || Category::path(&connection, category_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(path))
+}
+
+async fn get_recent_category_items(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(category_id): PathParam<i32>,
+    Query(params): Query<LimitQuery>,
+    Query(subtree): Query<Recursive>,
+) -> Result<Json<Vec<Item>>, HandlerError> {
+    let limit = params.limit(DEFAULT_RANKING_LIMIT, MAX_PAGE_SIZE)?;
+    retry_transient(
+        || Category::read_from_db_by_id(&connection, category_id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    let items = retry_transient(
+        || Category::recent_items(&connection, category_id, subtree.recursive, limit),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(items))
+}
+
+async fn add_category(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(truncation): Query<Truncation>,
+    JsonBody(mut payload): JsonBody<NewCategory>,
+) -> Result<(HeaderMap, Json<Category>), HandlerError> {
+    let warning = validation::fit_name_and_description(
+        &config,
+        &payload.name,
+        &mut payload.description,
+        truncation.truncate,
+    )?;
+    let mut transaction = connection.begin().await?;
+    let category_id = Category::insert_into_db(
+        &mut *transaction,
+        &payload.name,
+        &payload.description,
+        payload.parent_id,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    audit(
+        &mut transaction,
+        "category",
+        category_id,
+        Action::Create,
+        serde_json::to_value(&payload).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    let category = Category::read_from_db_by_id(&connection, category_id)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((warning, Json(category)))
+}
+
+async fn delete_category_by_id(
+    State(connection): State<PgPool>,
+    PathParam(category_id): PathParam<i32>,
+    Query(params): Query<DeleteCategoryParams>,
+) -> Result<(), HandlerError> {
+    if category_id == category::ROOT_ID {
+        return Err(HandlerError::new(
+            StatusCode::CONFLICT,
+            "The root category cannot be deleted".to_string(),
+        ));
+    }
+
+    if params.reassign_to == Some(category_id) {
+        return Err(HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            "Cannot reassign items to the category being deleted".to_string(),
+        ));
+    }
+
+    let mut transaction = connection.begin().await?;
+    Category::lock(&mut transaction, category_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    if let Some(reassign_to) = params.reassign_to {
+        Category::reassign_and_delete_from_db(&mut *transaction, category_id, reassign_to)
+            .await
+            .map_err(HandlerError::from_db)?;
+        audit(
+            &mut transaction,
+            "category",
+            category_id,
+            Action::Delete,
+            Some(serde_json::json!({ "reassign_to": reassign_to })),
+        )
+        .await?;
+        transaction.commit().await?;
+        return Ok(());
+    }
+
+    let item_count = Category::count_items(&mut *transaction, category_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    if item_count > 0 {
+        return Err(HandlerError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Category {} still has {} items, pass reassign_to to move them",
+                category_id, item_count
+            ),
+        ));
+    }
+
+    Category::delete_from_db(&mut *transaction, category_id)
+        .await
+        .map_err(HandlerError::from_db)?;
+    audit(
+        &mut transaction,
+        "category",
+        category_id,
+        Action::Delete,
+        None,
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn update_category(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+    Query(truncation): Query<Truncation>,
+    JsonBody(mut category): JsonBody<Category>,
+) -> Result<HeaderMap, HandlerError> {
+    let warning = validation::fit_name_and_description(
+        &config,
+        &category.name,
+        &mut category.description,
+        truncation.truncate,
+    )?;
+    if category.id == category::ROOT_ID {
+        return Err(HandlerError::new(
+            StatusCode::CONFLICT,
+            "The root category cannot be changed".to_string(),
+        ));
+    }
+    let since = unmodified_since(&headers)?;
+    if since.is_some() {
+        Category::read_from_db_by_id(&connection, category.id)
+            .await
+            .map_err(HandlerError::from_db)?;
+    }
+    let mut transaction = connection.begin().await?;
+    if let Some(parent_id) = category.parent_id {
+        Category::lock_tree(&mut transaction)
+            .await
+            .map_err(HandlerError::from_db)?;
+        let cycle = Category::in_subtree(&mut *transaction, category.id, parent_id)
+            .await
+            .map_err(HandlerError::from_db)?;
+        if cycle {
+            return Err(HandlerError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Category {} cannot be placed below itself or one of its descendants",
+                    category.id
+                ),
+            ));
+        }
+    }
+    let updated = Category::update_in_db(&mut *transaction, &category, since)
+        .await
+        .map_err(HandlerError::from_db)?;
+    ensure_updated(updated, since)?;
+    audit(
+        &mut transaction,
+        "category",
+        category.id,
+        Action::Update,
+        serde_json::to_value(&category).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(warning)
+}
+
+async fn get_all_pictures(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Page<PictureInfo>, HandlerError> {
+    let pictures = retry_transient(
+        || PictureInfo::read_from_db(&connection, pagination.limit(&config), pagination.offset()),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    pagination
+        .into_page(
+            &config,
+            pictures,
+            retry_transient(|| PictureInfo::count(&connection), config.db_read_attempts),
+        )
+        .await
+}
+
+async fn move_picture(
+    State(connection): State<PgPool>,
+    State(s3_permits): State<S3Permits>,
+    State(config): State<Arc<Config>>,
+    PathParam(picture_id): PathParam<i32>,
+    Query(params): Query<MovePicture>,
+) -> Result<(), HandlerError> {
+    ensure_storage(&config)?;
+    Item::read_from_db_by_id(&connection, params.to_item)
+        .await
+        .map_err(HandlerError::from_db)?;
+    let mut transaction = connection.begin().await?;
+    let released =
+        match PictureInfo::move_to_item(&mut *transaction, &s3_permits, picture_id, params.to_item)
+            .await
+            .map_err(HandlerError::from_db)?
+        {
+            Moved::Moved(released) => released,
+            Moved::AlreadyThere => {
+                return Err(HandlerError::new(
+                    StatusCode::CONFLICT,
+                    format!(
+                        "Picture {} already belongs to item {}",
+                        picture_id, params.to_item
+                    ),
+                ))
+            }
+        };
+    audit(
+        &mut transaction,
+        "picture",
+        picture_id,
+        Action::Update,
+        Some(serde_json::json!({ "item_id": params.to_item })),
+    )
+    .await?;
+    transaction.commit().await?;
+    PictureInfo::delete_unused_objects(&connection, &s3_permits, released).await;
+    Ok(())
+}
+
+async fn get_storage_usage(
+    State(connection): State<PgPool>,
+    State(s3_permits): State<S3Permits>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<StorageUsage>, HandlerError> {
+    ensure_storage(&config)?;
+    let usage = retry_transient(
+        || PictureInfo::storage_usage(&connection, &s3_permits),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(usage))
+}
+
+/// Reports dangling references and, when file storage is configured, pictures out of sync with it
+async fn get_integrity_report(
+    State(connection): State<PgPool>,
+    State(s3_permits): State<S3Permits>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<IntegrityReport>, HandlerError> {
+    let report = retry_transient(
+        || {
+            IntegrityReport::check(
+                &connection,
+                config.storage_configured.then_some(&s3_permits),
+            )
+        },
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(report))
+}
+
+/// Lists the ids of pictures whose object is missing from storage
+async fn get_broken_pictures(
+    State(connection): State<PgPool>,
+    State(s3_permits): State<S3Permits>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Vec<i32>>, HandlerError> {
+    ensure_storage(&config)?;
+    let broken = retry_transient(
+        || PictureInfo::broken_pictures(&connection, &s3_permits),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(broken))
+}
+
+/// Lists what object storage holds in a bucket, for diagnosing storage problems
+async fn get_s3_objects(
+    State(config): State<Arc<Config>>,
+    State(s3_permits): State<S3Permits>,
+    Query(listing): Query<ObjectListing>,
+) -> Result<Json<ObjectPage>, HandlerError> {
+    ensure_storage(&config)?;
+    if !picture::is_picture_bucket(&listing.bucket) {
+        return Err(HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Bucket `{}` does not hold pictures", listing.bucket),
+        ));
+    }
+    let limit = listing.limit.unwrap_or(config.default_page_size as usize);
+    let page = PictureInfo::list_objects(&s3_permits, &listing.bucket, listing.token, limit)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| {
+            HandlerError::new(
+                StatusCode::NOT_FOUND,
+                format!("Bucket `{}` does not exist", listing.bucket),
+            )
+        })?;
+    Ok(Json(page))
+}
+
+/// Corrects the cached picture counts of all items, returning how many were wrong
+async fn recount_all_pictures(State(connection): State<PgPool>) -> Result<Json<u64>, HandlerError> {
+    let corrected = Item::recount_all_pictures(&connection)
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(corrected))
+}
+
+async fn reload_settings(State(state): State<AppState>) -> Result<StatusCode, HandlerError> {
+    state
+        .reload_settings()
+        .await
+        .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_setting(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(key): PathParam<String>,
+) -> Result<Json<Setting>, HandlerError> {
+    let setting = retry_transient(
+        || Setting::read_from_db_by_key(&connection, &key),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(HandlerError::from_db)?;
+    Ok(Json(setting))
+}
+
+/// Stores and audits a setting after checking that it names a known field and holds a valid
+/// value, it takes effect on the next reload
+async fn put_setting(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    PathParam(key): PathParam<String>,
+    JsonBody(value): JsonBody<SettingValue>,
+) -> Result<Json<Setting>, HandlerError> {
+    let mut transaction = connection.begin().await?;
+    let setting = Setting::upsert_in_db(&mut *transaction, &key, &value.value)
+        .await
+        .map_err(HandlerError::from_db)?;
+    setting
+        .apply(&mut Config::clone(&config))
+        .map_err(|e| HandlerError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    audit(
+        &mut transaction,
+        "setting",
+        setting.id,
+        Action::Update,
+        serde_json::to_value(&setting).ok(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(Json(setting))
+}
+
+async fn get_audit_log(
+    State(connection): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, HandlerError> {
+    let entries = retry_transient(
+        || AuditEntry::read_for_entity(&connection, &query.entity, query.id),
+        config.db_read_attempts,
+    )
+    .await
+    .map_err(|e| HandlerError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroU64,
+        sync::{Arc, Mutex, Once},
+    };
+
+    use axum::{
+        middleware,
+        routing::{get, post},
+        Json, Router,
+    };
+    use chrono::{DateTime, TimeZone, Utc};
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use sqlx::PgPool;
+
+    use crate::{
+        audit::AuditEntry,
+        category::{Category, NewCategory, ROOT_ID},
+        config::{Config, RouteTimeout},
+        integrity::IntegrityReport,
+        item::{Item, ItemCard, ItemWithThumbnail, NewItem},
+        location::{Location, NewLocation, Occupancy},
+        note::Note,
+        picture::{ObjectPage, PictureInfo, PictureUrl, S3Permits, CONTENT_BUCKET},
+        router::{
+            create_router, enforce_timeout, limit_concurrency, parse_http_date, profile_endpoint,
+            to_camel_case, to_snake_case, Envelope, Profiling, Readiness, ReadinessStatus,
+            RequestCounter, RequestPermits, MAX_PAGE_SIZE,
+        },
+        settings::Setting,
+        state::AppState,
+        upsert::Upserted,
+    };
+
+    /// Keeps every log record so tests can assert on what middleware logged
+    struct CapturingLogger;
+
+    static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+    static LOGGER: CapturingLogger = CapturingLogger;
+    static INIT_LOGGER: Once = Once::new();
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            RECORDS
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn captured_logs() -> Vec<(Level, String)> {
+        INIT_LOGGER.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Debug);
+        });
+        RECORDS.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    pub async fn warn_on_slow_request() {
+        captured_logs();
+        let config = Config {
+            slow_request_ms: 50,
+            ..Default::default()
+        };
+        let router = Router::new()
+            .route(
+                "/slow-probe",
+                get(|| async { tokio::time::sleep(std::time::Duration::from_millis(100)).await }),
+            )
+            .route("/fast-probe", get(|| async {}))
+            .layer(middleware::from_fn_with_state(
+                Profiling {
+                    config: Arc::new(config),
+                    requests: RequestCounter::default(),
+                },
+                profile_endpoint,
+            ));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3028").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for path in ["slow-probe", "fast-probe"] {
+            client
+                .get(format!("http://localhost:3028/{}", path))
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let logs = captured_logs();
+        let warned = |path: &str| {
+            logs.iter()
+                .any(|(level, message)| *level == Level::Warn && message.contains(path))
+        };
+        assert!(warned("/slow-probe"));
+        assert!(!warned("/fast-probe"));
+        assert!(logs.iter().any(|(level, message)| *level == Level::Debug
+            && message.starts_with("Finished handling GET at /fast-probe")));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn redact_headers_in_logs() {
+        captured_logs();
+        let router = Router::new()
+            .route("/redact-probe", get(|| async {}))
+            .layer(middleware::from_fn_with_state(
+                Profiling {
+                    config: Arc::new(Config::default()),
+                    requests: RequestCounter::default(),
+                },
+                profile_endpoint,
+            ));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3035").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        client
+            .get("http://localhost:3035/redact-probe")
+            .header("Authorization", "Bearer hunter2")
+            .header("X-Request-Id", "abc")
+            .send()
+            .await
+            .unwrap();
+
+        let logs = captured_logs();
+        let handling = logs
+            .iter()
+            .find(|(_, message)| message.starts_with("Handling GET at /redact-probe"))
+            .map(|(_, message)| message.clone())
+            .unwrap();
+        assert!(handling.contains("authorization: ***"));
+        assert!(handling.contains("x-request-id: abc"));
+        assert!(!handling.contains("hunter2"));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn sample_detailed_logs() {
+        captured_logs();
+        let config = Config {
+            log_sample_rate: NonZeroU64::new(3).unwrap(),
+            ..Default::default()
+        };
+        let requests = RequestCounter::default();
+        let router = Router::new()
+            .route("/sample-probe", get(|| async {}))
+            .layer(middleware::from_fn_with_state(
+                Profiling {
+                    config: Arc::new(config),
+                    requests: requests.clone(),
+                },
+                profile_endpoint,
+            ));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3042").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for _ in 0..6 {
+            client
+                .get("http://localhost:3042/sample-probe")
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let logs = captured_logs();
+        let handled = logs
+            .iter()
+            .filter(|(_, message)| message.starts_with("Handling GET at /sample-probe"))
+            .count();
+        assert_eq!(handled, 2);
+        assert_eq!(requests.count(), 6);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn count_requests(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3075").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for _ in 0..3 {
+            client
+                .get("http://localhost:3075/status/health")
+                .send()
+                .await
+                .unwrap();
+        }
+        let count = client
+            .get("http://localhost:3075/status/requests")
+            .send()
+            .await
+            .unwrap()
+            .json::<u64>()
+            .await
+            .unwrap();
+        assert_eq!(count, 4);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_health(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get("http://localhost:3000/status/health")
+            .send()
+            .await
+            .unwrap();
+        let body = response.text().await.unwrap();
+        assert_eq!(body, "Healthy");
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_ready_when_healthy(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3026").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get("http://localhost:3026/status/ready")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let readiness: Readiness = response.json().await.unwrap();
+        assert_eq!(readiness.status, ReadinessStatus::Healthy);
+        assert!(readiness.db);
+        assert!(readiness.s3);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_ready_when_db_down(pool: PgPool) {
+        pool.close().await;
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3027").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get("http://localhost:3027/status/ready")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let readiness: Readiness = response.json().await.unwrap();
+        assert_eq!(readiness.status, ReadinessStatus::Down);
+        assert!(!readiness.db);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_location(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3001").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        client
+            .post("http://localhost:3001/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let locations: Vec<Location> = client
+            .get("http://localhost:3001/api/locations")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let location = locations.first().unwrap();
+
+        assert_eq!(location.name, "Kitchen".to_string());
+        assert_eq!(location.description, "Where we make food".to_string());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_location_by_id(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3002").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        client
+            .post("http://localhost:3002/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let location: Location = client
+            .get("http://localhost:3002/api/locations/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(location.name, "Kitchen".to_string());
+        assert_eq!(location.description, "Where we make food".to_string());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    #[allow(clippy::bool_assert_comparison)]
+    pub async fn delete_location_by_id(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3003").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        client
+            .post("http://localhost:3003/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let location: Location = client
+            .get("http://localhost:3003/api/locations/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(location.name, "Kitchen".to_string());
+        assert_eq!(location.description, "Where we make food".to_string());
+
+        client
+            .delete("http://localhost:3003/api/locations/1")
+            .send()
+            .await
+            .unwrap();
+
+        let locations: Vec<Location> = client
+            .get("http://localhost:3003/api/locations")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(locations.iter().any(|location| location.id == 1), false);
+
+        for url in [
+            "http://localhost:3003/api/locations/1",
+            "http://localhost:3003/api/items/1",
+            "http://localhost:3003/api/notes/1",
+        ] {
+            let response = client.delete(url).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND, "{}", url);
+        }
+        let entries: Vec<AuditEntry> = client
+            .get("http://localhost:3003/api/audit?entity=location&id=1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let actions = entries
+            .iter()
+            .map(|e| e.action.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(actions, vec!["create", "delete"]);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn update_location(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3004").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        client
+            .post("http://localhost:3004/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let mut location: Location = client
+            .get("http://localhost:3004/api/locations/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(location.name, "Kitchen".to_string());
+        assert_eq!(location.description, "Where we make food".to_string());
+
+        location.description = "Where i make food".to_string();
+
+        client
+            .put("http://localhost:3004/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let location2: Location = client
+            .get("http://localhost:3004/api/locations/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(location2.name, "Kitchen".to_string());
+        assert_eq!(location2.description, "Where i make food".to_string());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_category(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3005").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let category = NewCategory::new(
+            "Books".to_string(),
+            "Item where words are stored".to_string(),
+        );
+
+        client
+            .post("http://localhost:3005/api/categories")
+            .json(&category)
+            .send()
+            .await
+            .unwrap();
+
+        let categories: Vec<Category> = client
+            .get("http://localhost:3005/api/categories")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let category = categories
+            .iter()
+            .find(|category| category.id != ROOT_ID)
+            .unwrap();
+
+        assert_eq!(category.name, "Books".to_string());
+        assert_eq!(
+            category.description,
+            "Item where words are stored".to_string()
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_category_by_id(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3006").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let category = NewLocation::new(
+            "Books".to_string(),
+            "Item where words are stored".to_string(),
+        );
+
+        client
+            .post("http://localhost:3006/api/categories")
+            .json(&category)
+            .send()
+            .await
+            .unwrap();
+
+        let category: Category = client
+            .get("http://localhost:3006/api/categories/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(category.name, "Books".to_string());
+        assert_eq!(
+            category.description,
+            "Item where words are stored".to_string()
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    #[allow(clippy::bool_assert_comparison)]
+    pub async fn delete_category_by_id(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3007").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let category = NewCategory::new(
+            "Books".to_string(),
+            "Item where words are stored".to_string(),
+        );
+
+        client
+            .post("http://localhost:3007/api/categories")
+            .json(&category)
+            .send()
+            .await
+            .unwrap();
+
+        let category: Category = client
+            .get("http://localhost:3007/api/categories/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(category.name, "Books".to_string());
+        assert_eq!(
+            category.description,
+            "Item where words are stored".to_string()
+        );
+
+        client
+            .delete("http://localhost:3007/api/categories/1")
+            .send()
+            .await
+            .unwrap();
+
+        let categories: Vec<Category> = client
+            .get("http://localhost:3007/api/categories")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(categories.iter().any(|category| category.id == 1), false);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn update_category(pool: PgPool) {
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3008").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let category = NewCategory::new(
+            "Books".to_string(),
+            "Item where words are stored".to_string(),
+        );
+
+        client
+            .post("http://localhost:3008/api/categories")
+            .json(&category)
+            .send()
+            .await
+            .unwrap();
+
+        let mut category: Category = client
+            .get("http://localhost:3008/api/categories/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(category.name, "Books".to_string());
+        assert_eq!(
+            category.description,
+            "Item where words are stored".to_string()
+        );
+
+        category.description = "Item where words is stored".to_string();
+
+        client
+            .put("http://localhost:3008/api/categories")
+            .json(&category)
+            .send()
+            .await
+            .unwrap();
+
+        let category2: Category = client
+            .get("http://localhost:3008/api/categories/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(category2.name, "Books".to_string());
+        assert_eq!(
+            category2.description,
+            "Item where words is stored".to_string()
+        );
+        assert_eq!(category2.parent_id, Some(crate::category::ROOT_ID));
+
+        let novels = NewCategory {
+            parent_id: Some(1),
+            ..NewCategory::new("Novels".to_string(), "Books with stories".to_string())
+        };
+        client
+            .post("http://localhost:3008/api/categories")
+            .json(&novels)
+            .send()
+            .await
+            .unwrap();
+        for (id, parent_id, status) in [
+            (1, Some(1), reqwest::StatusCode::BAD_REQUEST),
+            (1, Some(2), reqwest::StatusCode::BAD_REQUEST),
+            (
+                crate::category::ROOT_ID,
+                None,
+                reqwest::StatusCode::CONFLICT,
+            ),
+        ] {
+            let response = client
+                .put("http://localhost:3008/api/categories")
+                .json(&serde_json::json!({
+                    "id": id,
+                    "name": "Books",
+                    "description": "Item where words are stored",
+                    "parent_id": parent_id,
+                }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{} below {:?}", id, parent_id);
+        }
+        let root = Category::read_from_db_by_id(&pool, crate::category::ROOT_ID)
+            .await
+            .unwrap();
+        assert_eq!(root.name, "all".to_string());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn default_page_size(pool: PgPool) {
+        let config = Config {
+            default_page_size: 2,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3009").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for name in ["Kitchen", "Garage", "Attic"] {
+            let location = NewLocation::new(name.to_string(), "Somewhere".to_string());
+            client
+                .post("http://localhost:3009/api/locations")
+                .json(&location)
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let locations: Vec<Location> = client
+            .get("http://localhost:3009/api/locations")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(locations.len(), 2);
+
+        let locations: Vec<Location> = client
+            .get("http://localhost:3009/api/locations?limit=3")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(locations.len(), 3);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn post_with_wrong_content_type(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3010").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post("http://localhost:3010/api/locations")
+            .header("Content-Type", "text/plain")
+            .body(r#"{"name": "Kitchen", "description": "Where we make food"}"#)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            response.text().await.unwrap(),
+            "Expected request with `Content-Type: application/json`"
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_locations_in_envelope(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3011").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for name in ["Kitchen", "Garage", "Attic"] {
+            let location = NewLocation::new(name.to_string(), "Somewhere".to_string());
+            client
+                .post("http://localhost:3011/api/locations")
+                .json(&location)
+                .send()
+                .await
+                .unwrap();
+        }
+
+        let envelope: Envelope<Location> = client
+            .get("http://localhost:3011/api/locations?envelope=true&limit=2&offset=1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(envelope.data.len(), 2);
+        assert_eq!(envelope.total, 3);
+        assert_eq!(envelope.limit, 2);
+        assert_eq!(envelope.offset, 1);
+
+        let locations: Vec<Location> = client
+            .get("http://localhost:3011/api/locations?limit=2")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(locations.len(), 2);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_locations_with_negative_pagination(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3070").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for query in ["limit=-1", "offset=-5", "limit=2&offset=-1"] {
+            let response = client
+                .get(format!("http://localhost:3070/api/locations?{}", query))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.status(),
+                reqwest::StatusCode::BAD_REQUEST,
+                "{}",
+                query
+            );
+        }
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_locations_clamps_limit(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3071").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let envelope: Envelope<Location> = client
+            .get("http://localhost:3071/api/locations?envelope=true&limit=1000000")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(envelope.limit, MAX_PAGE_SIZE);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_category_with_items_conflicts(pool: PgPool) {
+        Category::insert_into_db(&pool, "Books", "Item where words are stored", None)
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(1),
+                ..NewItem::new("Dune", "Sand and worms", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3012").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .delete("http://localhost:3012/api/categories/1")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+        assert!(response.text().await.unwrap().contains("still has 1 items"));
+
+        let category: Category = client
+            .get("http://localhost:3012/api/categories/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(category.name, "Books".to_string());
+
+        let response = client
+            .delete("http://localhost:3012/api/categories/99")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let poetry = Category::insert_into_db(&pool, "Poetry", "Short lines", None)
+            .await
+            .unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        Item::insert_into_db(
+            &mut *transaction,
+            &NewItem {
+                category_id: Some(poetry),
+                ..NewItem::new("Haiku", "Seventeen syllables", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+        let delete = tokio::spawn(
+            client
+                .delete(format!("http://localhost:3012/api/categories/{}", poetry))
+                .send(),
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        transaction.commit().await.unwrap();
+        let response = delete.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_category_reassigning_items(pool: PgPool) {
+        Category::insert_into_db(&pool, "Books", "Item where words are stored", None)
+            .await
+            .unwrap();
+        Category::insert_into_db(&pool, "Novels", "Books with stories", None)
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(1),
+                ..NewItem::new("Dune", "Sand and worms", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3013").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .delete("http://localhost:3013/api/categories/1?reassign_to=99")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "No category 99 to reassign items to"
+        );
+
+        let response = client
+            .delete("http://localhost:3013/api/categories/1?reassign_to=2")
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+
+        let categories: Vec<Category> = client
+            .get("http://localhost:3013/api/categories")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert!(!categories.iter().any(|category| category.id == 1));
+
+        let item = Item::read_from_db_by_id(&pool, 1).await.unwrap();
+        assert_eq!(item.category_id, Some(2));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_category_with_children(pool: PgPool) {
+        Category::insert_into_db(&pool, "Books", "Item where words are stored", None)
+            .await
+            .unwrap();
+        Category::insert_into_db(&pool, "Novels", "Books with stories", Some(1))
+            .await
+            .unwrap();
+
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3069").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .delete("http://localhost:3069/api/categories/1")
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+
+        let category: Category = client
+            .get("http://localhost:3069/api/categories/2")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(category.parent_id, Some(crate::category::ROOT_ID));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_pictures_to_item(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3014").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let form = reqwest::multipart::Form::new()
+            .part(
+                "Stol forfra",
+                reqwest::multipart::Part::bytes(b"add_pictures_to_item front".to_vec())
+                    .file_name("front.png"),
+            )
+            .part(
+                "Stol bakfra",
+                reqwest::multipart::Part::bytes(b"add_pictures_to_item back".to_vec())
+                    .file_name("back.png"),
+            );
+
+        let picture_ids: Vec<i32> = client
+            .post("http://localhost:3014/api/items/1/pictures/batch")
+            .multipart(form)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(picture_ids.len(), 2);
+        assert_eq!(PictureInfo::count(&pool).await.unwrap(), 2);
+
+        let response = client
+            .post("http://localhost:3014/api/items/2/pictures/batch")
+            .multipart(reqwest::multipart::Form::new())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn cors_allowlist(pool: PgPool) {
+        let config = Config {
+            cors_origins: vec![
+                "http://localhost:8000".to_string(),
+                "https://items.example.com".to_string(),
+            ],
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3015").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for origin in ["http://localhost:8000", "https://items.example.com"] {
+            let response = client
+                .get("http://localhost:3015/api/locations")
+                .header("Origin", origin)
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response
+                    .headers()
+                    .get("access-control-allow-origin")
+                    .unwrap(),
+                origin
+            );
+        }
+
+        let response = client
+            .get("http://localhost:3015/api/locations")
+            .header("Origin", "https://evil.example.com")
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response
+            .headers()
+            .get("access-control-allow-origin")
+            .is_none());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_item_card(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+        PictureInfo::insert_into_db(
+            &pool,
+            &S3Permits::new(Config::default().s3_max_concurrency),
+            1,
+            "Bilde av stol",
+            b"get_item_card",
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3016").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let card: ItemCard = client
+            .get("http://localhost:3016/api/items/1/card")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(card.item.id, 1);
+        assert_eq!(card.pictures.len(), 1);
+        assert!(!card.pictures[0].url.is_empty());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn update_is_audited(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3017").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        client
+            .post("http://localhost:3017/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let mut location: Location = client
+            .get("http://localhost:3017/api/locations/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        location.description = "Where I make food".to_string();
+
+        client
+            .put("http://localhost:3017/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        let entries: Vec<AuditEntry> = client
+            .get("http://localhost:3017/api/audit?entity=location&id=1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, "create".to_string());
+        assert_eq!(entries[1].action, "update".to_string());
+        assert_eq!(entries[1].actor, "anonymous".to_string());
+        assert_eq!(
+            entries[1].diff.as_ref().unwrap()["description"],
+            "Where I make food"
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn failed_audit_rolls_back(pool: PgPool) {
+        sqlx::query("DROP TABLE audit_log")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3074").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        let response = client
+            .post("http://localhost:3074/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let (locations,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM locations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(locations, 0);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_item_with_invalid_id(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3031").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get("http://localhost:3031/api/items/abc")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "Invalid path parameter `abc`, it must be an integer"
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn post_with_missing_field(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3018").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post("http://localhost:3018/api/items")
+            .json(&serde_json::json!({ "name": "Stol", "date_origin": Utc::now() }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let message = response.text().await.unwrap();
+        assert!(message.contains("missing field `description`"));
+
+        let response = client
+            .post("http://localhost:3018/api/locations")
+            .json(&serde_json::json!({ "name": 42, "description": "Where we make food" }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let message = response.text().await.unwrap();
+        assert!(message.contains("name: invalid type"));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_items_with_projection(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3019").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let items: Vec<serde_json::Value> = client
+            .get("http://localhost:3019/api/items?fields=id,name")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["id"], 1);
+        assert_eq!(items[0]["name"], "Stol");
+        assert!(items[0].get("description").is_none());
+
+        let response = client
+            .get("http://localhost:3019/api/items?fields=id,secret")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(response.text().await.unwrap(), "Unknown field `secret`");
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_item_with_name_taken_in_location(pool: PgPool) {
+        let drawer = Location::insert_into_db(&pool, "Drawer", "Small drawer", None, None)
+            .await
+            .unwrap();
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Tall shelf", None, None)
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                location_id: Some(drawer),
+                ..NewItem::new("Hammer", "For nails", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3032").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for (location_id, status) in [
+            (Some(drawer), reqwest::StatusCode::CONFLICT),
+            (Some(shelf), reqwest::StatusCode::OK),
+            (None, reqwest::StatusCode::OK),
+            (None, reqwest::StatusCode::OK),
+        ] {
+            let item = serde_json::json!({
+                "name": "hammer",
+                "description": "Another one",
+                "date_origin": Utc::now(),
+                "location_id": location_id,
+            });
+
+            let response = client
+                .post("http://localhost:3032/api/items")
+                .json(&item)
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), status);
+        }
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_item_to_full_location(pool: PgPool) {
+        Location::insert_into_db(&pool, "Drawer", "Small drawer", None, Some(1))
+            .await
+            .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3020").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let item = serde_json::json!({
+            "name": "Hammer",
+            "description": "For nails",
+            "date_origin": Utc::now(),
+            "location_id": 1,
+        });
+
+        let response = client
+            .post("http://localhost:3020/api/items")
+            .json(&item)
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
+
+        let response = client
+            .post("http://localhost:3020/api/items")
+            .json(&item)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+        let occupancy: Occupancy = client
+            .get("http://localhost:3020/api/locations/1/occupancy")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(occupancy.capacity, Some(1));
+        assert_eq!(occupancy.used, 1);
+        assert_eq!(occupancy.remaining, Some(0));
+
+        let shelf = serde_json::json!({
+            "name": "Shelf",
+            "description": "Small shelf",
+            "capacity": 1,
+        });
+        client
+            .post("http://localhost:3020/api/locations")
+            .json(&shelf)
+            .send()
+            .await
+            .unwrap();
+        let add = |name: &'static str| {
+            client
+                .post("http://localhost:3020/api/items")
+                .json(&serde_json::json!({
+                    "name": name,
+                    "description": "For nails",
+                    "location_id": 2,
+                }))
+                .send()
+        };
+        let (first, second) = tokio::join!(add("Saw"), add("Drill"));
+        let mut statuses = vec![first.unwrap().status(), second.unwrap().status()];
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            vec![reqwest::StatusCode::OK, reqwest::StatusCode::CONFLICT]
+        );
+
+        let response = client
+            .post("http://localhost:3020/api/locations")
+            .json(&serde_json::json!({
+                "name": "Box",
+                "description": "Negative space",
+                "capacity": -1,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_root_category_conflicts(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3023").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .delete("http://localhost:3023/api/categories/0")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+        let root: Category = client
+            .get("http://localhost:3023/api/categories/0")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(root.name, "all".to_string());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn cache_control_headers(pool: PgPool) {
+        let config = Config {
+            cache_max_age_secs: 60,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3024").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
+
+        let response = client
+            .post("http://localhost:3024/api/locations")
+            .json(&location)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers().get("cache-control").unwrap(), "no-store");
+
+        let response = client
+            .get("http://localhost:3024/api/locations/1")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.headers().get("cache-control").unwrap(),
+            "max-age=60"
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn receive_items_at_location(pool: PgPool) {
+        let shelf = Location::insert_into_db(&pool, "Shelf", "Books", None, None)
+            .await
+            .unwrap();
+        let mut ids = Vec::new();
+        for name in ["Dune", "Emma", "Ulysses"] {
+            ids.push(
+                Item::insert_into_db(&pool, &NewItem::new(name, "Book", Utc::now()))
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3025").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let moved: u64 = client
+            .post(format!(
+                "http://localhost:3025/api/locations/{}/receive",
+                shelf
+            ))
+            .json(&ids)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(moved, 3);
+        for id in &ids {
+            let item = Item::read_from_db_by_id(&pool, *id).await.unwrap();
+            assert_eq!(item.location_id, Some(shelf));
+        }
+
+        let moved: u64 = client
+            .post(format!(
+                "http://localhost:3025/api/locations/{}/receive",
+                shelf
+            ))
+            .json(&ids[..1])
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(moved, 0);
+        let entries = AuditEntry::read_for_entity(&pool, "item", ids[0])
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let response = client
+            .post(format!(
+                "http://localhost:3025/api/locations/{}/receive",
+                shelf
+            ))
+            .json(&[ids[0], 999])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        assert!(AuditEntry::read_for_entity(&pool, "item", 999)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let response = client
+            .post(format!(
+                "http://localhost:3025/api/locations/{}/receive",
+                shelf + 1
+            ))
+            .json(&Vec::<i32>::new())
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_item_by_external_ref(pool: PgPool) {
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                external_ref: Some("SKU-42".to_string()),
+                ..NewItem::new("Stol", "Noe å sitte på", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3029").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let item: Item = client
+            .get("http://localhost:3029/api/items/by-ref/SKU-42")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(item.external_ref, Some("SKU-42".to_string()));
+
+        let response = client
+            .get("http://localhost:3029/api/items/by-ref/SKU-43")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_location_items_recursively(pool: PgPool) {
+        let garage = Location::insert_into_db(&pool, "Garage", "Cars and tools", None, None)
+            .await
+            .unwrap();
+        let shelf = Location::insert_into_db(&pool, "Shelf", "In the garage", Some(garage), None)
+            .await
+            .unwrap();
+        let car = Item::insert_into_db(
+            &pool,
+            &NewItem {
+                location_id: Some(garage),
+                ..NewItem::new("Car", "Red", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+        let hammer = Item::insert_into_db(
+            &pool,
+            &NewItem {
+                location_id: Some(shelf),
+                ..NewItem::new("Hammer", "For nails", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3030").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let items: Vec<Item> = client
+            .get(format!(
+                "http://localhost:3030/api/locations/{}/items",
+                garage
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(items.iter().map(|i| i.id).collect::<Vec<_>>(), vec![car]);
+
+        let items: Vec<Item> = client
+            .get(format!(
+                "http://localhost:3030/api/locations/{}/items?recursive=true",
+                garage
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            items.iter().map(|i| i.id).collect::<Vec<_>>(),
+            vec![car, hammer]
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_item_qr_code(pool: PgPool) {
+        let item_id =
+            Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+                .await
+                .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3033").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!(
+                "http://localhost:3033/api/items/{}/qr.png?size=128",
+                item_id
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.headers().get("content-type").unwrap(), "image/png");
+        let png = response.bytes().await.unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        let response = client
+            .get(format!(
+                "http://localhost:3033/api/items/{}/qr.png?size=4096",
+                item_id
+            ))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn scan_item(pool: PgPool) {
+        let item_id =
+            Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+                .await
+                .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3034").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let item: Item = client
+            .get("http://localhost:3034/api/scan")
+            .query(&[("code", format!("items://{}", item_id))])
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(item.id, item_id);
+
+        let response = client
+            .get("http://localhost:3034/api/scan?code=not-a-code")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let response = client
+            .get("http://localhost:3034/api/scan")
+            .query(&[("code", format!("items://{}", item_id + 1))])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn stale_update_is_rejected(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3036").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let item: Item = client
+            .get("http://localhost:3036/api/items/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let read_at = item
+            .updated_at
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        for status in [
+            reqwest::StatusCode::OK,
+            reqwest::StatusCode::PRECONDITION_FAILED,
+        ] {
+            let response = client
+                .put("http://localhost:3036/api/items")
+                .header("If-Unmodified-Since", &read_at)
+                .json(&item)
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), status);
+        }
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn export_items_as_json_lines(pool: PgPool) {
+        for name in ["Stol", "Bord", "Lampe"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Møbel", Utc::now()))
+                .await
+                .unwrap();
+        }
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3037").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get("http://localhost:3037/api/items/export.jsonl")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/x-ndjson"
+        );
+        let body = response.text().await.unwrap();
+        let items = body
+            .lines()
+            .map(|line| serde_json::from_str::<Item>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items.iter().map(|i| i.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_items_with_thumbnails(pool: PgPool) {
+        for name in ["Stol", "Bord"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Møbel", Utc::now()))
+                .await
+                .unwrap();
+        }
+        PictureInfo::insert_into_db(
+            &pool,
+            &S3Permits::new(Config::default().s3_max_concurrency),
+            1,
+            "Bilde av stol",
+            b"get_items_with_thumbnails",
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3038").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let items: Vec<ItemWithThumbnail> = client
+            .get("http://localhost:3038/api/items?with_thumbnails=true")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert!(items[0].thumbnail_url.is_some());
+        assert!(items[1].thumbnail_url.is_none());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_pictures_without_storage(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+        let config = Config {
+            storage_configured: false,
+            ..Default::default()
+        };
+
+        let router = create_router(AppState::new(pool.clone(), config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3039").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let form = reqwest::multipart::Form::new().part(
+            "Stol forfra",
+            reqwest::multipart::Part::bytes(vec![1, 2, 3]).file_name("front.png"),
+        );
+
+        let response = client
+            .post("http://localhost:3039/api/items/1/pictures/batch")
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "File storage not configured"
+        );
+        assert_eq!(PictureInfo::count(&pool).await.unwrap(), 0);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn read_pictures_without_storage(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+        let config = Config {
+            storage_configured: false,
+            ..Default::default()
+        };
+
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3073").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for path in ["/api/items?with_thumbnails=true", "/api/items/1/card"] {
+            let response = client
+                .get(format!("http://localhost:3073{}", path))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.status(),
+                reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                "{}",
+                path
+            );
+        }
+        for request in [
+            client.delete("http://localhost:3073/api/items/1/pictures"),
+            client.post("http://localhost:3073/api/pictures/1/move?to_item=1"),
+        ] {
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_item_with_too_long_name(pool: PgPool) {
+        let config = Config {
+            max_name_len: 10,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3040").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let item = serde_json::json!({
+            "name": "A chair far too long to name",
+            "description": "Noe å sitte på",
+            "date_origin": Utc::now(),
+        });
+
+        let response = client
+            .post("http://localhost:3040/api/items")
+            .json(&item)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "`name` is longer than 10 characters"
+        );
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_integrity_report_without_storage(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+        let config = Config {
+            storage_configured: false,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3041").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get("http://localhost:3041/api/admin/integrity")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let report: IntegrityReport = response.json().await.unwrap();
+        assert!(report.dangling_references.is_empty());
+        assert!(!report.storage_checked);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_location_tree(pool: PgPool) {
+        let garage = Location::insert_into_db(&pool, "Garage", "Cars and tools", None, None)
+            .await
+            .unwrap();
+        let wall = Location::insert_into_db(&pool, "Wall", "In the garage", Some(garage), None)
+            .await
+            .unwrap();
+        Location::insert_into_db(&pool, "Shelf", "On the wall", Some(wall), None)
+            .await
+            .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3043").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let tree: serde_json::Value = client
+            .get(format!(
+                "http://localhost:3043/api/locations/{}/tree",
+                garage
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(tree["location"]["name"], "Garage");
+        assert_eq!(tree["children"][0]["location"]["name"], "Wall");
+        assert_eq!(
+            tree["children"][0]["children"][0]["location"]["name"],
+            "Shelf"
+        );
+        assert_eq!(
+            tree["children"][0]["children"][0]["children"],
+            serde_json::json!([])
+        );
+
+        let response = client
+            .get("http://localhost:3043/api/locations/999/tree")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_similar_items(pool: PgPool) {
+        let furniture = Category::insert_into_db(&pool, "Furniture", "Things to sit on", None)
+            .await
+            .unwrap();
+        let chair = Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(furniture),
+                ..NewItem::new("Stol", "Noe å sitte på", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+        Item::insert_into_db(&pool, &NewItem::new("Sykkel", "To hjul", Utc::now()))
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(furniture),
+                ..NewItem::new("Bord", "Noe å spise ved", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3044").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let items: Vec<Item> = client
+            .get(format!(
+                "http://localhost:3044/api/items/{}/similar?limit=1",
+                chair
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "Bord");
+
+        let response = client
+            .get("http://localhost:3044/api/items/999/similar")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn reload_settings(pool: PgPool) {
+        for name in ["Stol", "Bord", "Lampe"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3045").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .put("http://localhost:3045/api/admin/settings/default_page_size")
+            .json(&serde_json::json!({ "value": "2" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let items: Vec<Item> = client
+            .get("http://localhost:3045/api/items")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 3);
+
+        let response = client
+            .post("http://localhost:3045/api/admin/settings/reload")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+
+        let items: Vec<Item> = client
+            .get("http://localhost:3045/api/items")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 2);
+
+        let setting: Setting = client
+            .get("http://localhost:3045/api/admin/settings/default_page_size")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(setting.value, "2".to_string());
+
+        let entries: Vec<AuditEntry> = client
+            .get(format!(
+                "http://localhost:3045/api/audit?entity=setting&id={}",
+                setting.id
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].diff.as_ref().unwrap()["value"], "2");
+
+        let response = client
+            .put("http://localhost:3045/api/admin/settings/colour")
+            .json(&serde_json::json!({ "value": "blue" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(response.text().await.unwrap(), "Unknown setting `colour`");
+
+        let response = client
+            .put("http://localhost:3045/api/admin/settings/default_page_size")
+            .json(&serde_json::json!({ "value": "0" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let entries: Vec<AuditEntry> = client
+            .get(format!(
+                "http://localhost:3045/api/audit?entity=setting&id={}",
+                setting.id
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_untouched_items(pool: PgPool) {
+        let chair = Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+            .await
+            .unwrap();
+        Item::insert_into_db(&pool, &NewItem::new("Bord", "Test", Utc::now()))
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3046").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let mut item: Item = client
+            .get(format!("http://localhost:3046/api/items/{}", chair))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        item.description = "Noe å sitte på".to_string();
+        let response = client
+            .put("http://localhost:3046/api/items")
+            .json(&item)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let page: Envelope<Item> = client
+            .get("http://localhost:3046/api/items/untouched?envelope=true")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(page.total, 1);
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.data[0].name, "Bord");
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn ensure_category(pool: PgPool) {
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3047").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let category = serde_json::json!({
+            "name": "Books",
+            "description": "Place to read words",
+        });
+
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let upserted: Upserted = client
+                .put("http://localhost:3047/api/categories/ensure")
+                .json(&category)
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            responses.push(upserted);
+        }
+
+        assert!(responses[0].created);
+        assert!(!responses[1].created);
+        assert_eq!(responses[0].id, responses[1].id);
+        let books = Category::read_from_db(&pool, 100, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|c| c.name == "Books")
+            .count();
+        assert_eq!(books, 1);
+
+        let response = client
+            .put("http://localhost:3047/api/categories/ensure")
+            .json(&serde_json::json!({
+                "name": "Novels",
+                "description": "Books with stories",
+                "parent_id": 999,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_location_holding_items(pool: PgPool) {
+        let kitchen = Location::insert_into_db(&pool, "Kitchen", "Where we make food", None, None)
+            .await
+            .unwrap();
+        let pan = Item::insert_into_db(
+            &pool,
+            &NewItem {
+                location_id: Some(kitchen),
+                ..NewItem::new("Pan", "For frying", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3048").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .delete(format!("http://localhost:3048/api/locations/{}", kitchen))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+        assert_eq!(
+            response.text().await.unwrap(),
+            format!(
+                "Location {} still holds 1 items, pass force=true to leave them without a location",
+                kitchen
+            )
+        );
+        assert!(Location::read_from_db_by_id(&pool, kitchen).await.is_ok());
+
+        let response = client
+            .delete(format!(
+                "http://localhost:3048/api/locations/{}?force=true",
+                kitchen
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(Location::read_from_db_by_id(&pool, kitchen).await.is_err());
+        let pan = Item::read_from_db_by_id(&pool, pan).await.unwrap();
+        assert_eq!(pan.location_id, None);
+
+        let cellar = Location::insert_into_db(&pool, "Cellar", "Cold storage", None, None)
+            .await
+            .unwrap();
+        let mut transaction = pool.begin().await.unwrap();
+        Item::insert_into_db(
+            &mut *transaction,
+            &NewItem {
+                location_id: Some(cellar),
+                ..NewItem::new("Jam", "Strawberry", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+        let delete = tokio::spawn(
+            client
+                .delete(format!("http://localhost:3048/api/locations/{}", cellar))
+                .send(),
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        transaction.commit().await.unwrap();
+        let response = delete.await.unwrap().unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+        assert!(Location::read_from_db_by_id(&pool, cellar).await.is_ok());
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_s3_objects(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+        PictureInfo::insert_into_db(
+            &pool,
+            &S3Permits::new(Config::default().s3_max_concurrency),
+            1,
+            "Stol forfra",
+            b"get_s3_objects",
+        )
+        .await
+        .unwrap();
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3049").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let page: ObjectPage = client
+            .get(format!(
+                "http://localhost:3049/api/admin/s3/objects?bucket={}",
+                CONTENT_BUCKET
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let hash = sha256::digest(&[4u8, 5, 6, 7][..]);
+        assert!(page
+            .objects
+            .iter()
+            .any(|object| object.key == hash && object.size == 4));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_s3_objects_of_other_buckets(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3076").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for bucket in ["backups", "item-", "item-one", "pictures-old"] {
+            let response = client
+                .get(format!(
+                    "http://localhost:3076/api/admin/s3/objects?bucket={}",
+                    bucket
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                reqwest::StatusCode::BAD_REQUEST,
+                "{}",
+                bucket
+            );
+        }
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_item_truncating_description(pool: PgPool) {
+        let config = Config {
+            max_description_len: 10,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool.clone(), config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3050").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let item = serde_json::json!({
+            "name": "Stol",
+            "description": "Noe å sitte på ved bordet",
+            "date_origin": Utc::now(),
+        });
+
+        let response = client
+            .post("http://localhost:3050/api/items")
+            .json(&item)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let response = client
+            .post("http://localhost:3050/api/items?truncate=true")
+            .json(&item)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(
+            response.headers()["warning"],
+            "199 - \"`description` was truncated to 10 characters\""
+        );
+        let created: Item = response.json().await.unwrap();
+        assert_eq!(created.name, "Stol".to_string());
+        assert_eq!(created.description, "Noe å sitt".to_string());
+
+        let items = Item::read_from_db(&pool, 100, 0).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, created.id);
+        assert_eq!(items[0].description, "Noe å sitt".to_string());
+
+        let response = client
+            .post("http://localhost:3050/api/locations?truncate=true")
+            .json(&NewLocation::new(
+                "Kjøkken".to_string(),
+                "Der vi lager mat".to_string(),
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response.headers().contains_key("warning"));
+        let location: Location = response.json().await.unwrap();
+        assert_eq!(location.name, "Kjøkken".to_string());
+        assert_eq!(location.description, "Der vi lag".to_string());
+
+        let response = client
+            .post("http://localhost:3050/api/categories?truncate=true")
+            .json(&NewCategory::new(
+                "Bøker".to_string(),
+                "Ting med ord i".to_string(),
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response.headers().contains_key("warning"));
+        let category: Category = response.json().await.unwrap();
+        assert_eq!(category.name, "Bøker".to_string());
+        assert_eq!(category.description, "Ting med o".to_string());
+        assert_eq!(category.parent_id, Some(crate::category::ROOT_ID));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn move_picture(pool: PgPool) {
+        for name in ["Stol", "Bord"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location, position) VALUES (1, 'Bilde', 'a', $1, 1)")
+            .bind(CONTENT_BUCKET)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3051").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post("http://localhost:3051/api/pictures/1/move?to_item=999")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let response = client
+            .post("http://localhost:3051/api/pictures/1/move?to_item=2")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let item_ids = sqlx::query_scalar::<_, i32>("SELECT item_id FROM pictures")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(item_ids, vec![2]);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_item_picture_urls(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Noe å sitte på", Utc::now()))
+            .await
+            .unwrap();
+        let front = PictureInfo::insert_into_db(
+            &pool,
+            &S3Permits::new(Config::default().s3_max_concurrency),
+            1,
+            "Stol forfra",
+            b"get_item_picture_urls front",
+        )
+        .await
+        .unwrap();
+        let back = PictureInfo::insert_into_db(
+            &pool,
+            &S3Permits::new(Config::default().s3_max_concurrency),
+            1,
+            "Stol bakfra",
+            b"get_item_picture_urls back",
+        )
+        .await
+        .unwrap();
+
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3052").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let urls: Vec<PictureUrl> = client
+            .get("http://localhost:3052/api/items/1/pictures/urls")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let ids = urls.iter().map(|u| u.picture_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![front, back]);
+        assert!(urls.iter().all(|u| !u.url.is_empty()));
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn shed_requests_over_concurrency_limit() {
+        let permits = RequestPermits::new(1, std::time::Duration::from_millis(50));
+        let router = Router::new()
+            .route(
+                "/slow-probe",
+                get(|| async { tokio::time::sleep(std::time::Duration::from_millis(300)).await }),
+            )
+            .route("/status/health", get(|| async {}))
+            .layer(middleware::from_fn_with_state(permits, limit_concurrency));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3053").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let requests = (0..3).map(|_| client.get("http://localhost:3053/slow-probe").send());
+        let health = async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            client
+                .get("http://localhost:3053/status/health")
+                .send()
+                .await
+                .unwrap()
+                .status()
+        };
+        let (responses, health) = tokio::join!(futures::future::join_all(requests), health);
+
+        let statuses = responses
+            .into_iter()
+            .map(|response| response.unwrap().status())
+            .collect::<Vec<_>>();
+        assert!(statuses.contains(&reqwest::StatusCode::OK));
+        assert!(statuses.contains(&reqwest::StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(health, reqwest::StatusCode::OK);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn item_notes(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Lader", "Til mobilen", Utc::now()))
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3054").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let mut added = Vec::new();
+        for body in ["Lånt ut til Ola", "Må repareres"] {
+            let note: Note = client
+                .post("http://localhost:3054/api/items/1/notes")
+                .json(&serde_json::json!({ "body": body }))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            added.push(note);
+        }
+
+        let response = client
+            .post("http://localhost:3054/api/items/1/notes")
+            .json(&serde_json::json!({ "body": "  " }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(response.text().await.unwrap(), "`body` must not be empty");
+
+        let notes: Vec<Note> = client
+            .get("http://localhost:3054/api/items/1/notes")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let bodies = notes.iter().map(|n| n.body.as_str()).collect::<Vec<_>>();
+        assert_eq!(bodies, vec!["Må repareres", "Lånt ut til Ola"]);
+
+        client
+            .delete(format!("http://localhost:3054/api/notes/{}", added[0].id))
+            .send()
+            .await
+            .unwrap();
+
+        let notes: Vec<Note> = client
+            .get("http://localhost:3054/api/items/1/notes")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].id, added[1].id);
+
+        let response = client
+            .get("http://localhost:3054/api/items/2/notes")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_items_in_value_range(pool: PgPool) {
+        for (name, value_cents) in [("Stol", Some(500)), ("Bord", None), ("Sofa", Some(90000))] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    value_cents,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3055").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let page: Envelope<Item> = client
+            .get("http://localhost:3055/api/items?min_value=100&max_value=1000&envelope=true")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.data[0].name, "Stol");
+
+        let response = client
+            .get("http://localhost:3055/api/items?min_value=1000&max_value=100")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn get_item_category_coverage_in_value_range(pool: PgPool) {
+        for (name, description) in [("Furniture", "Things to sit on"), ("Tools", "To fix with")] {
+            Category::insert_into_db(&pool, name, description, None)
+                .await
+                .unwrap();
+        }
+        for (name, category_id, value_cents) in [
+            ("Stol", 1, Some(500)),
+            ("Hammer", 2, Some(2500)),
+            ("Sag", 2, None),
+        ] {
+            Item::insert_into_db(
+                &pool,
+                &NewItem {
+                    category_id: Some(category_id),
+                    value_cents,
+                    ..NewItem::new(name, "Test", Utc::now())
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3072").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let coverage: serde_json::Value = client
+            .get("http://localhost:3072/api/items/category-coverage")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(coverage["distinct_categories"], 2);
+
+        let coverage: serde_json::Value = client
+            .get("http://localhost:3072/api/items/category-coverage?min_value=100&max_value=1000")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(coverage["distinct_categories"], 1);
+
+        let response = client
+            .get("http://localhost:3072/api/items/category-coverage?min_value=1000&max_value=100")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[test]
+    pub fn converts_key_case() {
+        assert_eq!(to_camel_case("date_origin"), "dateOrigin");
+        assert_eq!(to_camel_case("id"), "id");
+        assert_eq!(to_snake_case("dateOrigin"), "date_origin");
+        assert_eq!(to_snake_case("date_origin"), "date_origin");
+    }
+
+    #[test]
+    pub fn parses_http_dates() {
+        let expected = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+        for value in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(value), Some(expected), "{}", value);
+        }
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[sqlx::test]
+    pub async fn camel_case_json(pool: PgPool) {
+        let config = Config {
+            camel_case: true,
+            max_json_body_bytes: 4 * 1024 * 1024,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3056").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post("http://localhost:3056/api/items")
+            .json(&serde_json::json!({
+                "name": "Stol",
+                "description": "Noe å sitte på",
+                "dateOrigin": Utc::now(),
+                "valueCents": 500,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let item: serde_json::Value = client
+            .get("http://localhost:3056/api/items/1")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(item.get("dateOrigin").is_some());
+        assert!(item.get("date_origin").is_none());
+        assert_eq!(item["valueCents"], 500);
+        let keys = item.as_object().unwrap().keys().take(4).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["id", "name", "description", "dateOrigin"]);
+
+        // Bodies up to the configured limit reach the handler, even above axum's default
+        let response = client
+            .post("http://localhost:3056/api/items")
+            .json(&serde_json::json!({
+                "name": "Stol",
+                "description": "x".repeat(3 * 1024 * 1024),
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn delete_item_pictures(pool: PgPool) {
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3057").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        let removed: u64 = client
+            .delete("http://localhost:3057/api/items/1/pictures")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(removed, 0);
+
+        let response = client
+            .delete("http://localhost:3057/api/items/999/pictures")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn post_item_without_date_origin(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3058").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
-
-        client
-            .post("http://localhost:3001/api/locations")
-            .json(&location)
+        let response = client
+            .post("http://localhost:3058/api/items")
+            .json(&serde_json::json!({ "name": "Stol", "description": "Noe å sitte på" }))
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
 
-        let locations: Vec<Location> = client
-            .get("http://localhost:3001/api/locations")
+        let item: serde_json::Value = client
+            .get("http://localhost:3058/api/items/1")
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
-
-        let location = locations.first().unwrap();
-
-        assert_eq!(location.name, "Kitchen".to_string());
-        assert_eq!(location.description, "Where we make food".to_string());
+        let date_origin: DateTime<Utc> =
+            serde_json::from_value(item["date_origin"].clone()).unwrap();
+        assert!((Utc::now() - date_origin).num_seconds().abs() < 5);
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn get_location_by_id(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn pretty_json(pool: PgPool) {
+        Location::insert_into_db(&pool, "Kjøkken", "Der vi lager mat", None, None)
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool, Config::default()));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3002").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3059").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
-
-        client
-            .post("http://localhost:3002/api/locations")
-            .json(&location)
+        let compact = client
+            .get("http://localhost:3059/api/locations/1")
             .send()
             .await
+            .unwrap()
+            .text()
+            .await
             .unwrap();
+        assert!(!compact.contains('\n'));
 
-        let location: Location = client
-            .get("http://localhost:3002/api/locations/1")
+        let pretty = client
+            .get("http://localhost:3059/api/locations/1?pretty=true")
             .send()
             .await
             .unwrap()
-            .json()
+            .text()
             .await
             .unwrap();
+        assert!(pretty.starts_with("{\n  \"id\": 1,\n  \"name\": \"Kjøkken\""));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
 
-        assert_eq!(location.name, "Kitchen".to_string());
-        assert_eq!(location.description, "Where we make food".to_string());
+        let response = client
+            .get("http://localhost:3059/api/locations/1?pretty=maybe")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn delete_location_by_id(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn swap_item_order(pool: PgPool) {
+        for name in ["Stol", "Bord", "Lampe"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Test", Utc::now()))
+                .await
+                .unwrap();
+        }
+        let before = Item::read_from_db_by_id(&pool, 1).await.unwrap();
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3003").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3060").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
-
-        client
-            .post("http://localhost:3003/api/locations")
-            .json(&location)
+        let response = client
+            .post("http://localhost:3060/api/items/swap-order")
+            .json(&serde_json::json!({ "first": 1, "second": 3 }))
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
 
-        let location: Location = client
-            .get("http://localhost:3003/api/locations/1")
+        let items: Vec<Item> = client
+            .get("http://localhost:3060/api/items")
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
+        let names = items.iter().map(|i| i.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Lampe", "Bord", "Stol"]);
+        let after = Item::read_from_db_by_id(&pool, 1).await.unwrap();
+        assert_eq!(after.updated_at, before.updated_at);
 
-        assert_eq!(location.name, "Kitchen".to_string());
-        assert_eq!(location.description, "Where we make food".to_string());
-
-        client
-            .delete("http://localhost:3003/api/locations/1")
+        let response = client
+            .post("http://localhost:3060/api/items/swap-order")
+            .json(&serde_json::json!({ "first": 1, "second": 999 }))
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
 
-        let locations: Vec<Location> = client
-            .get("http://localhost:3003/api/locations")
+        let response = client
+            .post("http://localhost:3060/api/items/swap-order")
+            .json(&serde_json::json!({ "first": 2, "second": 2 }))
             .send()
             .await
-            .unwrap()
-            .json()
-            .await
             .unwrap();
-
-        assert_eq!(locations.iter().any(|location| location.id == 1), false);
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn update_location(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn post_oversized_json(pool: PgPool) {
+        let config = Config {
+            max_json_body_bytes: 1024,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3004").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3061").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let location = NewLocation::new("Kitchen".to_string(), "Where we make food".to_string());
-
-        client
-            .post("http://localhost:3004/api/locations")
-            .json(&location)
+        let response = client
+            .post("http://localhost:3061/api/items")
+            .json(&serde_json::json!({
+                "name": "Stol",
+                "description": "Noe å sitte på ".repeat(100),
+                "date_origin": Utc::now(),
+            }))
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
 
-        let mut location: Location = client
-            .get("http://localhost:3004/api/locations/1")
+        let response = client
+            .post("http://localhost:3061/api/items")
+            .json(&serde_json::json!({ "name": "Stol", "description": "Noe å sitte på" }))
             .send()
             .await
-            .unwrap()
-            .json()
-            .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
 
-        assert_eq!(location.name, "Kitchen".to_string());
-        assert_eq!(location.description, "Where we make food".to_string());
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
 
-        location.description = "Where i make food".to_string();
+    #[sqlx::test]
+    pub async fn enforce_route_timeouts(pool: PgPool) {
+        let config = Config {
+            request_timeout_ms: 1000,
+            route_timeouts: vec![
+                "/api/items=50".parse().unwrap(),
+                "/api/items/uploads=1000".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert!("/api/items".parse::<RouteTimeout>().is_err());
+        let slow = || async { tokio::time::sleep(std::time::Duration::from_millis(200)).await };
+        let router = Router::new()
+            .route("/api/items/slow", get(slow))
+            .route("/api/items/uploads/slow", get(slow))
+            .route("/api/items-archive/slow", get(slow))
+            .route("/api/items/pictures/slow", post(slow))
+            .route("/api/locations/slow", get(slow))
+            .layer(middleware::from_fn_with_state(
+                AppState::new(pool, config),
+                enforce_timeout,
+            ));
 
-        client
-            .put("http://localhost:3004/api/locations")
-            .json(&location)
-            .send()
-            .await
-            .unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3062").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
 
-        let location2: Location = client
-            .get("http://localhost:3004/api/locations/1")
+        let client = reqwest::Client::new();
+
+        for (path, status) in [
+            ("/api/items/slow", reqwest::StatusCode::GATEWAY_TIMEOUT),
+            ("/api/items-archive/slow", reqwest::StatusCode::OK),
+            ("/api/items/uploads/slow", reqwest::StatusCode::OK),
+            ("/api/locations/slow", reqwest::StatusCode::OK),
+        ] {
+            let response = client
+                .get(format!("http://localhost:3062{}", path))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", path);
+        }
+        let response = client
+            .post("http://localhost:3062/api/items/pictures/slow")
+            .multipart(reqwest::multipart::Form::new().text("front", "picture"))
             .send()
             .await
-            .unwrap()
-            .json()
-            .await
             .unwrap();
-
-        assert_eq!(location2.name, "Kitchen".to_string());
-        assert_eq!(location2.description, "Where i make food".to_string());
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn add_category(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn get_random_item(pool: PgPool) {
+        let books = Category::insert_into_db(&pool, "Books", "Place to read words", None)
+            .await
+            .unwrap();
+        let router = create_router(AppState::new(pool.clone(), Config::default()));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3005").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3063").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let category = NewCategory::new(
-            "Books".to_string(),
-            "Item where words are stored".to_string(),
-        );
-
-        client
-            .post("http://localhost:3005/api/categories")
-            .json(&category)
+        let response = client
+            .get("http://localhost:3063/api/items/random")
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
 
-        let categories: Vec<Category> = client
-            .get("http://localhost:3005/api/categories")
+        Item::insert_into_db(&pool, &NewItem::new("Stol", "Test", Utc::now()))
+            .await
+            .unwrap();
+        Item::insert_into_db(
+            &pool,
+            &NewItem {
+                category_id: Some(books),
+                ..NewItem::new("Dune", "Sand and worms", Utc::now())
+            },
+        )
+        .await
+        .unwrap();
+
+        let item: Item = client
+            .get("http://localhost:3063/api/items/random")
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
+        assert!(["Stol", "Dune"].contains(&item.name.as_str()));
 
-        let category = categories.first().unwrap();
+        let item: Item = client
+            .get(format!(
+                "http://localhost:3063/api/items/random?category_id={}",
+                books
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(item.name, "Dune");
 
-        assert_eq!(category.name, "Books".to_string());
-        assert_eq!(
-            category.description,
-            "Item where words are stored".to_string()
-        );
+        let response = client
+            .get(format!(
+                "http://localhost:3063/api/items/random?category_id={}",
+                books + 1
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn get_category_by_id(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn export_pictures_as_csv(pool: PgPool) {
+        for name in ["Stol", "Bord"] {
+            Item::insert_into_db(&pool, &NewItem::new(name, "Møbel", Utc::now()))
+                .await
+                .unwrap();
+        }
+        for (item_id, description, hash, size) in [
+            (1, "Forfra", "a", Some(3)),
+            (2, "Bakfra, med \"ben\"", "b", Some(5)),
+            (2, "Gammel", "c", None),
+        ] {
+            sqlx::query("INSERT INTO pictures (item_id, description, hash, object_storage_location, position, size) VALUES ($1, $2, $3, $4, (SELECT COUNT(*) + 1 FROM pictures WHERE item_id = $1), $5)")
+                .bind(item_id)
+                .bind(description)
+                .bind(hash)
+                .bind(CONTENT_BUCKET)
+                .bind(size)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let router = create_router(AppState::new(pool, Config::default()));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3006").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3064").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let category = NewLocation::new(
-            "Books".to_string(),
-            "Item where words are stored".to_string(),
-        );
-
-        client
-            .post("http://localhost:3006/api/categories")
-            .json(&category)
+        let response = client
+            .get("http://localhost:3064/api/pictures.csv")
             .send()
             .await
             .unwrap();
+        assert_eq!(response.headers().get("content-type").unwrap(), "text/csv");
+        assert_eq!(
+            response.text().await.unwrap(),
+            "id,item_id,description,hash,size\n1,1,Forfra,a,3\n2,2,\"Bakfra, med \"\"ben\"\"\",b,5\n3,2,Gammel,c,\n"
+        );
 
-        let category: Category = client
-            .get("http://localhost:3006/api/categories/1")
+        let body = client
+            .get("http://localhost:3064/api/pictures.csv?item_id=2")
             .send()
             .await
             .unwrap()
-            .json()
+            .text()
             .await
             .unwrap();
-
-        assert_eq!(category.name, "Books".to_string());
-        assert_eq!(
-            category.description,
-            "Item where words are stored".to_string()
-        );
+        let item_ids = body
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(item_ids, vec!["2", "2"]);
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn delete_category_by_id(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn post_json_above_default_limit(pool: PgPool) {
+        let config = Config {
+            max_json_body_bytes: 4 * 1024 * 1024,
+            ..Default::default()
+        };
+        let router = create_router(AppState::new(pool, config));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3007").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3065").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let category = NewCategory::new(
-            "Books".to_string(),
-            "Item where words are stored".to_string(),
-        );
-
-        client
-            .post("http://localhost:3007/api/categories")
-            .json(&category)
+        // Reaches the handler, which rejects the description as too long
+        let response = client
+            .post("http://localhost:3065/api/items")
+            .json(&serde_json::json!({
+                "name": "Stol",
+                "description": "x".repeat(3 * 1024 * 1024),
+            }))
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
 
-        let category: Category = client
-            .get("http://localhost:3007/api/categories/1")
-            .send()
-            .await
-            .unwrap()
-            .json()
-            .await
-            .unwrap();
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
 
-        assert_eq!(category.name, "Books".to_string());
-        assert_eq!(
-            category.description,
-            "Item where words are stored".to_string()
-        );
+    #[tokio::test]
+    pub async fn pretty_json_passes_large_responses_through() {
+        let router = Router::new()
+            .route(
+                "/big-probe",
+                get(|| async { Json(vec!["x".repeat(1024); 17 * 1024]) }),
+            )
+            .layer(middleware::from_fn(crate::router::pretty_json));
 
-        client
-            .delete("http://localhost:3007/api/categories/1")
-            .send()
-            .await
-            .unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3066").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
 
-        let categories: Vec<Category> = client
-            .get("http://localhost:3007/api/categories")
-            .send()
-            .await
-            .unwrap()
-            .json()
+        let response = reqwest::get("http://localhost:3066/big-probe?pretty=true")
             .await
             .unwrap();
-
-        assert_eq!(categories.iter().any(|category| category.id == 1), false);
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body = response.text().await.unwrap();
+        assert!(!body.contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<Vec<String>>(&body).unwrap().len(),
+            17 * 1024
+        );
 
         handle.abort();
         assert!(handle.await.is_err());
     }
 
     #[sqlx::test]
-    pub async fn update_category(pool: PgPool) {
-        let router = create_router(pool);
+    pub async fn add_category_with_taken_name(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
 
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3008").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3067").await.unwrap();
         let handle = tokio::spawn(async move {
             axum::serve(listener, router).await.unwrap();
         });
 
         let client = reqwest::Client::new();
 
-        let category = NewCategory::new(
-            "Books".to_string(),
-            "Item where words are stored".to_string(),
-        );
-
-        client
-            .post("http://localhost:3008/api/categories")
-            .json(&category)
-            .send()
-            .await
-            .unwrap();
+        for (name, status) in [
+            ("Books", reqwest::StatusCode::OK),
+            ("Games", reqwest::StatusCode::OK),
+            ("Books", reqwest::StatusCode::CONFLICT),
+        ] {
+            let response = client
+                .post("http://localhost:3067/api/categories")
+                .json(&NewCategory::new(name.to_string(), "Things".to_string()))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
 
-        let mut category: Category = client
-            .get("http://localhost:3008/api/categories/1")
+        let mut games: Category = client
+            .get("http://localhost:3067/api/categories/2")
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
-
-        assert_eq!(category.name, "Books".to_string());
-        assert_eq!(
-            category.description,
-            "Item where words are stored".to_string()
-        );
-
-        category.description = "Item where words is stored".to_string();
-
-        client
-            .put("http://localhost:3008/api/categories")
-            .json(&category)
+        assert_eq!(games.name, "Games");
+        games.name = "Books".to_string();
+        let response = client
+            .put("http://localhost:3067/api/categories")
+            .json(&games)
             .send()
             .await
             .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
 
-        let category2: Category = client
-            .get("http://localhost:3008/api/categories/1")
+        handle.abort();
+        assert!(handle.await.is_err());
+    }
+
+    #[sqlx::test]
+    pub async fn add_location_with_taken_name(pool: PgPool) {
+        let router = create_router(AppState::new(pool, Config::default()));
+
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:3068").await.unwrap();
+        let handle = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        let client = reqwest::Client::new();
+
+        for (name, status) in [
+            ("Kjøkken", reqwest::StatusCode::OK),
+            ("Stue", reqwest::StatusCode::OK),
+            ("Kjøkken", reqwest::StatusCode::CONFLICT),
+        ] {
+            let response = client
+                .post("http://localhost:3068/api/locations")
+                .json(&serde_json::json!({ "name": name, "description": "Rom" }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        let mut living_room: Location = client
+            .get("http://localhost:3068/api/locations/2")
             .send()
             .await
             .unwrap()
             .json()
             .await
             .unwrap();
-
-        assert_eq!(category2.name, "Books".to_string());
-        assert_eq!(
-            category2.description,
-            "Item where words is stored".to_string()
-        );
+        assert_eq!(living_room.name, "Stue");
+        living_room.name = "Kjøkken".to_string();
+        let response = client
+            .put("http://localhost:3068/api/locations")
+            .json(&living_room)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
 
         handle.abort();
         assert!(handle.await.is_err());
@@ -0,0 +1,153 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+use crate::config::Config;
+
+/// Runtime setting stored in the database, overriding the value given on the command line
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Setting {
+    /// Set by the database, identifies the setting in the audit log
+    #[serde(default)]
+    pub id: i32,
+    pub key: String,
+    pub value: String,
+}
+
+/// Parses a setting value into the type of the configuration field it overrides
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value `{}` for setting `{}`", value, key))
+}
+
+/// Parses a setting value that must be at least 1
+fn parse_positive<T: FromStr + PartialOrd + From<u8>>(key: &str, value: &str) -> Result<T> {
+    let parsed = parse(key, value)?;
+    if parsed < T::from(1) {
+        bail!("Setting `{}` must be at least 1, got {}", key, value);
+    }
+    Ok(parsed)
+}
+
+impl Setting {
+    pub async fn read_from_db(pool: &PgPool) -> Result<Vec<Setting>> {
+        let settings = sqlx::query_as::<_, Setting>("SELECT * FROM settings ORDER BY key")
+            .fetch_all(pool)
+            .await?;
+        Ok(settings)
+    }
+
+    pub async fn read_from_db_by_key(pool: &PgPool, key: &str) -> Result<Setting> {
+        let setting = sqlx::query_as::<_, Setting>("SELECT * FROM settings s WHERE s.key = $1")
+            .bind(key)
+            .fetch_one(pool)
+            .await?;
+        Ok(setting)
+    }
+
+    /// Stores a setting, replacing its previous value
+    pub async fn upsert_in_db(
+        executor: impl PgExecutor<'_>,
+        key: &str,
+        value: &str,
+    ) -> Result<Setting> {
+        let setting = sqlx::query_as::<_, Setting>(
+            "INSERT INTO settings (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value RETURNING *",
+        )
+        .bind(key)
+        .bind(value)
+        .fetch_one(executor)
+        .await?;
+        Ok(setting)
+    }
+
+    /// Overrides the configuration field named by the setting, failing on unknown keys, values
+    /// that do not parse and values out of range. Only `cache_max_age_secs` may be 0
+    pub fn apply(&self, config: &mut Config) -> Result<()> {
+        let (key, value) = (self.key.as_str(), self.value.as_str());
+        match key {
+            "default_page_size" => config.default_page_size = parse_positive(key, value)?,
+            "presign_expiry_secs" => config.presign_expiry_secs = parse_positive(key, value)?,
+            "cache_max_age_secs" => config.cache_max_age_secs = parse(key, value)?,
+            "slow_request_ms" => config.slow_request_ms = parse_positive(key, value)?,
+            "log_sample_rate" => config.log_sample_rate = parse(key, value)?,
+            "max_name_len" => config.max_name_len = parse_positive(key, value)?,
+            "max_description_len" => config.max_description_len = parse_positive(key, value)?,
+            _ => bail!("Unknown setting `{}`", key),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    pub async fn upsert_and_apply(pool: PgPool) {
+        Setting::upsert_in_db(&pool, "default_page_size", "20")
+            .await
+            .unwrap();
+        let setting = Setting::upsert_in_db(&pool, "default_page_size", "25")
+            .await
+            .unwrap();
+        assert_eq!(setting.value, "25".to_string());
+        assert_eq!(Setting::read_from_db(&pool).await.unwrap().len(), 1);
+
+        let mut config = Config::default();
+        setting.apply(&mut config).unwrap();
+        assert_eq!(config.default_page_size, 25);
+    }
+
+    #[test]
+    pub fn apply_rejects_bad_settings() {
+        let mut config = Config::default();
+        let unknown = Setting {
+            id: 0,
+            key: "colour".to_string(),
+            value: "blue".to_string(),
+        };
+        assert!(unknown.apply(&mut config).is_err());
+        let unparsable = Setting {
+            id: 0,
+            key: "max_name_len".to_string(),
+            value: "many".to_string(),
+        };
+        assert!(unparsable.apply(&mut config).is_err());
+        assert_eq!(config.max_name_len, 255);
+        for value in ["0", "-3"] {
+            let empty_pages = Setting {
+                id: 0,
+                key: "default_page_size".to_string(),
+                value: value.to_string(),
+            };
+            assert!(empty_pages.apply(&mut config).is_err());
+        }
+        assert_eq!(config.default_page_size, 100);
+        for key in [
+            "presign_expiry_secs",
+            "slow_request_ms",
+            "log_sample_rate",
+            "max_name_len",
+            "max_description_len",
+        ] {
+            let zero = Setting {
+                id: 0,
+                key: key.to_string(),
+                value: "0".to_string(),
+            };
+            assert!(zero.apply(&mut config).is_err(), "{}", key);
+        }
+        let no_caching = Setting {
+            id: 0,
+            key: "cache_max_age_secs".to_string(),
+            value: "0".to_string(),
+        };
+        assert!(no_caching.apply(&mut config).is_ok());
+    }
+}
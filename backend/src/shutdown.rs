@@ -0,0 +1,206 @@
+use std::{
+    future::{Future, IntoFuture},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+    Router,
+};
+use log::{info, warn};
+use tokio::{net::TcpListener, sync::Notify, time::Instant};
+
+/// Number of requests currently being handled
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Decrements the in-flight count when the request is done, even if it is cancelled
+struct InFlightGuard(InFlight);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        (self.0).0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Middleware counting the requests currently being handled
+pub async fn track_in_flight(
+    State(in_flight): State<InFlight>,
+    request: Request,
+    next: Next,
+) -> Response {
+    in_flight.0.fetch_add(1, Ordering::SeqCst);
+    let _guard = InFlightGuard(in_flight);
+    next.run(request).await
+}
+
+/// Completes when the process receives Ctrl+C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Serves the router until `signal` completes, then stops accepting connections and
+/// waits at most `grace` for in-flight requests before returning
+pub async fn serve_with_drain<F>(
+    listener: TcpListener,
+    router: Router,
+    in_flight: InFlight,
+    signal: F,
+    grace: Duration,
+) -> Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let signalled = Arc::new(Notify::new());
+    let notify = signalled.clone();
+    let server = axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            signal.await;
+            notify.notify_one();
+        })
+        .into_future();
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return Ok(result?),
+        _ = signalled.notified() => {},
+    }
+
+    info!(
+        "Shutting down, draining {} in-flight requests for up to {} ms",
+        in_flight.count(),
+        grace.as_millis()
+    );
+    let deadline = Instant::now() + grace;
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            result = &mut server => return Ok(result?),
+            _ = tokio::time::sleep_until(deadline) => {
+                warn!(
+                    "Grace period elapsed with {} requests in flight, forcing shutdown",
+                    in_flight.count()
+                );
+                return Ok(());
+            },
+            _ = ticker.tick() => info!("Draining, {} requests in flight", in_flight.count()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use axum::{middleware, routing::get};
+    use tokio::sync::oneshot;
+
+    fn slow_router(in_flight: InFlight, delay: Duration) -> Router {
+        Router::new()
+            .route(
+                "/slow",
+                get(move || async move {
+                    tokio::time::sleep(delay).await;
+                    "done"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                in_flight.clone(),
+                track_in_flight,
+            ))
+    }
+
+    async fn wait_for_in_flight(in_flight: &InFlight) {
+        while in_flight.count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    pub async fn forces_shutdown_after_grace() {
+        let in_flight = InFlight::default();
+        let router = slow_router(in_flight.clone(), Duration::from_secs(10));
+        let listener = TcpListener::bind("0.0.0.0:3021").await.unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_drain(
+            listener,
+            router,
+            in_flight.clone(),
+            async move {
+                rx.await.ok();
+            },
+            Duration::from_millis(200),
+        ));
+
+        let request = tokio::spawn(reqwest::get("http://localhost:3021/slow"));
+        wait_for_in_flight(&in_flight).await;
+
+        let now = Instant::now();
+        tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+
+        assert!(now.elapsed() < Duration::from_secs(5));
+        assert_eq!(in_flight.count(), 1);
+        request.abort();
+    }
+
+    #[tokio::test]
+    pub async fn drains_requests_within_grace() {
+        let in_flight = InFlight::default();
+        let router = slow_router(in_flight.clone(), Duration::from_millis(300));
+        let listener = TcpListener::bind("0.0.0.0:3022").await.unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_drain(
+            listener,
+            router,
+            in_flight.clone(),
+            async move {
+                rx.await.ok();
+            },
+            Duration::from_secs(5),
+        ));
+
+        let request = tokio::spawn(reqwest::get("http://localhost:3022/slow"));
+        wait_for_in_flight(&in_flight).await;
+
+        tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        assert_eq!(in_flight.count(), 0);
+    }
+}
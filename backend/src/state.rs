@@ -0,0 +1,140 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::extract::FromRef;
+use sqlx::PgPool;
+
+use crate::{
+    config::Config,
+    picture::S3Permits,
+    router::{Profiling, RequestCounter, RequestPermits},
+    settings::Setting,
+    shutdown::InFlight,
+};
+
+/// Shared dependencies handed to every handler as router state
+#[derive(Debug, Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    /// Configuration given at startup, before stored settings are applied
+    pub base_config: Arc<Config>,
+    config: Arc<RwLock<Arc<Config>>>,
+    pub in_flight: InFlight,
+    pub requests: RequestCounter,
+    pub permits: RequestPermits,
+    pub s3_permits: S3Permits,
+}
+
+impl AppState {
+    /// Creates a new [`AppState`].
+    pub fn new(pool: PgPool, config: Config) -> Self {
+        let permits = RequestPermits::new(
+            config.max_concurrent_requests,
+            Duration::from_millis(config.queue_timeout_ms),
+        );
+        let s3_permits = S3Permits::new(config.s3_max_concurrency);
+        let config = Arc::new(config);
+        Self {
+            pool,
+            base_config: config.clone(),
+            config: Arc::new(RwLock::new(config)),
+            in_flight: InFlight::default(),
+            requests: RequestCounter::default(),
+            permits,
+            s3_permits,
+        }
+    }
+}
+
+impl AppState {
+    /// Configuration currently in effect
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Applies the settings stored in the database on top of the startup configuration. The
+    /// configuration in effect is left alone if any setting is invalid
+    pub async fn reload_settings(&self) -> Result<()> {
+        let mut config = Config::clone(&self.base_config);
+        for setting in Setting::read_from_db(&self.pool).await? {
+            setting.apply(&mut config)?;
+        }
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for InFlight {
+    fn from_ref(state: &AppState) -> Self {
+        state.in_flight.clone()
+    }
+}
+
+impl FromRef<AppState> for RequestCounter {
+    fn from_ref(state: &AppState) -> Self {
+        state.requests.clone()
+    }
+}
+
+impl FromRef<AppState> for Profiling {
+    fn from_ref(state: &AppState) -> Self {
+        Self {
+            config: state.config(),
+            requests: state.requests.clone(),
+        }
+    }
+}
+
+impl FromRef<AppState> for RequestPermits {
+    fn from_ref(state: &AppState) -> Self {
+        state.permits.clone()
+    }
+}
+
+impl FromRef<AppState> for S3Permits {
+    fn from_ref(state: &AppState) -> Self {
+        state.s3_permits.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    pub async fn extracts_pool_and_config(pool: PgPool) {
+        let config = Config {
+            default_page_size: 7,
+            ..Default::default()
+        };
+        let state = AppState::new(pool, config);
+
+        let config = Arc::<Config>::from_ref(&state);
+        assert_eq!(config.default_page_size, 7);
+        assert!(Arc::ptr_eq(&config, &state.config()));
+
+        let pool = PgPool::from_ref(&state);
+        let one = sqlx::query_scalar::<_, i32>("SELECT 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(one, 1);
+    }
+}
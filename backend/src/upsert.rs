@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// Outcome of an upsert, telling whether the row was created or updated
+#[derive(FromRow, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Upserted {
+    pub id: i32,
+    pub created: bool,
+}
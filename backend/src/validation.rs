@@ -0,0 +1,114 @@
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use serde::Deserialize;
+
+use crate::{config::Config, error::HandlerError};
+
+/// Rejects a text field with 400 when it is longer than `max` characters
+pub fn ensure_max_len(field: &str, value: &str, max: usize) -> Result<(), HandlerError> {
+    if value.chars().count() > max {
+        return Err(HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            format!("`{}` is longer than {} characters", field, max),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects a text field with 400 when it is empty or only whitespace
+pub fn ensure_not_blank(field: &str, value: &str) -> Result<(), HandlerError> {
+    if value.trim().is_empty() {
+        return Err(HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            format!("`{}` must not be empty", field),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects a number with 400 when it is negative
+pub fn ensure_non_negative(field: &str, value: Option<i32>) -> Result<(), HandlerError> {
+    if value.is_some_and(|value| value < 0) {
+        return Err(HandlerError::new(
+            StatusCode::BAD_REQUEST,
+            format!("`{}` must not be negative", field),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the name and description shared by items, locations and categories against the configured limits
+pub fn ensure_name_and_description(
+    config: &Config,
+    name: &str,
+    description: &str,
+) -> Result<(), HandlerError> {
+    ensure_max_len("name", name, config.max_name_len)?;
+    ensure_max_len("description", description, config.max_description_len)
+}
+
+/// Query parameters letting a write shorten an over-long description instead of failing
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct Truncation {
+    #[serde(default)]
+    pub truncate: bool,
+}
+
+/// Checks the name and description like [`ensure_name_and_description`], but when `truncate` is
+/// set shortens an over-long description to the limit. The returned headers carry a `Warning`
+/// when the description was shortened
+pub fn fit_name_and_description(
+    config: &Config,
+    name: &str,
+    description: &mut String,
+    truncate: bool,
+) -> Result<HeaderMap, HandlerError> {
+    let mut headers = HeaderMap::new();
+    let max = config.max_description_len;
+    if truncate {
+        if let Some((end, _)) = description.char_indices().nth(max) {
+            description.truncate(end);
+            headers.insert(
+                header::WARNING,
+                HeaderValue::from_str(&format!(
+                    "199 - \"`description` was truncated to {} characters\"",
+                    max
+                ))
+                .expect("warning is valid header text"),
+            );
+        }
+    }
+    ensure_name_and_description(config, name, description)?;
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn counts_characters_not_bytes() {
+        assert!(ensure_max_len("name", "Blåbærsyltetøy", 14).is_ok());
+
+        let error = ensure_max_len("name", "Blåbærsyltetøy", 13).unwrap_err();
+
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.message, "`name` is longer than 13 characters");
+    }
+
+    #[test]
+    pub fn truncates_description_on_request() {
+        let config = Config {
+            max_description_len: 5,
+            ..Default::default()
+        };
+
+        let mut description = "Blåbærsyltetøy".to_string();
+        let headers =
+            fit_name_and_description(&config, "Syltetøy", &mut description, true).unwrap();
+        assert_eq!(description, "Blåbæ");
+        assert!(headers.contains_key(header::WARNING));
+
+        let mut description = "Blåbærsyltetøy".to_string();
+        assert!(fit_name_and_description(&config, "Syltetøy", &mut description, false).is_err());
+    }
+}